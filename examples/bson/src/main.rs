@@ -9,7 +9,7 @@ fn main() -> Result<()> {
         "./db/nosql.nodb",
         DumpPolicy::Auto,
         SerializationMethod::Cbor,
-    );
+    )?;
     for _ in 0..50 {
         let random_id: isize = trng.gen_range(0..isize::MAX);
         let user = User::new(random_id, "John Doe");
@@ -8,13 +8,15 @@ fn main() -> Result<()> {
         "./db/database.nodb",
         DumpPolicy::Auto,
         SerializationMethod::Cbor,
-    );
+    )?;
+    let mut batch = db.batch();
     for _ in 0..50 {
         let random_id: usize = trng.gen_range(usize::MIN..usize::MAX);
         let user = User::new(random_id, "John Doe");
         let key = format!("user_{}", random_id);
-        db.set(key, &user)?;
+        batch.set(key, &user)?;
     }
+    db.write_batch(batch)?;
     Ok(())
 }
 
@@ -0,0 +1,292 @@
+//! # Backup
+//!
+//! Incremental and rotating backups of a NoDb instance.
+//!
+//! A chain of backups starts with a full backup, which has the same format as the DB file.
+//! Each incremental backup that follows has the format of the write-ahead log: its first line
+//! holds the checksum of the previous backup in the chain, followed by the records that bring
+//! every key changed since that backup to its current state.
+//!
+//! Rotating backups are full backups written next to the DB file, named after it with the time
+//! they were taken in milliseconds since the Unix epoch (`<db>.<millis>.bak`).
+
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{read, read_dir, remove_file},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    crypto::checksum,
+    data::DbDataMut,
+    ser::Serializer,
+    wal::{decode, encode, header, LogOp},
+};
+
+/// The keys changed since the last backup of a NoDb instance.
+pub(crate) struct BackupState {
+    base: u64,
+    changed: HashSet<String>,
+}
+
+impl BackupState {
+    /// Starts tracking changes on top of the backup with the given content.
+    pub(crate) fn new(backup: &[u8]) -> Self {
+        BackupState::from_checksum(checksum(backup))
+    }
+
+    /// Starts tracking changes on top of the backup with the given checksum.
+    pub(crate) fn from_checksum(base: u64) -> Self {
+        BackupState {
+            base,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Records the keys changed by an operation.
+    pub(crate) fn track(&mut self, op: &LogOp) {
+        for key in op.keys() {
+            self.track_key(key);
+        }
+    }
+
+    /// Records a changed key.
+    pub(crate) fn track_key(&mut self, key: &str) {
+        self.changed.insert(key.to_string());
+    }
+
+    /// Returns the content of an incremental backup of the keys changed since the last backup.
+    ///
+    /// `key_ops` returns the records that bring a key to its current state.
+    pub(crate) fn incremental<F>(&self, ser: &Serializer, key_ops: F) -> Result<String>
+    where
+        F: Fn(&str) -> Vec<LogOp>,
+    {
+        let mut keys = self.changed.iter().collect::<Vec<_>>();
+        keys.sort();
+
+        let mut content = header(self.base);
+        for key in keys {
+            for op in key_ops(key) {
+                content.push_str(&encode(ser, op)?);
+            }
+        }
+        Ok(content)
+    }
+}
+
+/// Applies an incremental backup written on top of the backup with checksum `base`.
+///
+/// Returns the checksum of the incremental backup, which the next backup in the chain is based on.
+pub(crate) fn apply_incremental<P: AsRef<Path>>(
+    path: P,
+    base: u64,
+    ser: &Serializer,
+    mut data: DbDataMut<'_>,
+) -> Result<u64> {
+    let path = path.as_ref();
+    let content = read(path)?;
+    let mut lines = content.split_inclusive(|byte| *byte == b'\n');
+    if lines.next() != Some(header(base).as_bytes()) {
+        return Err(anyhow!(
+            "{} doesn't follow the previous backup in the chain",
+            path.display()
+        ));
+    }
+
+    for line in lines {
+        let op = line
+            .strip_suffix(b"\n")
+            .and_then(|line| decode(ser, line))
+            .ok_or_else(|| anyhow!("Corrupted record in {}", path.display()))?;
+        op.apply(&mut data);
+    }
+    Ok(checksum(&content))
+}
+
+/// Returns the path of a new rotating backup of the DB at `db_path`, more recent than any other.
+pub(crate) fn rotated_path<P: AsRef<Path>>(db_path: P) -> Result<PathBuf> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_millis())
+        .unwrap_or(0);
+    let stamp = match backup_stamps(&db_path)?.last() {
+        Some((last, _)) if *last >= now => last + 1,
+        _ => now,
+    };
+
+    let mut path = OsString::from(db_path.as_ref().as_os_str());
+    path.push(format!(".{}.bak", stamp));
+    Ok(PathBuf::from(path))
+}
+
+/// Lists the rotating backups of the DB at `db_path`, oldest first.
+pub(crate) fn rotated_backups<P: AsRef<Path>>(db_path: P) -> Result<Vec<PathBuf>> {
+    Ok(backup_stamps(db_path)?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect())
+}
+
+/// Removes the oldest rotating backups of the DB at `db_path`, keeping the `keep` most recent ones.
+pub(crate) fn prune_backups<P: AsRef<Path>>(db_path: P, keep: usize) -> Result<()> {
+    let backups = rotated_backups(db_path)?;
+    let excess = backups.len().saturating_sub(keep);
+    for backup in &backups[..excess] {
+        remove_file(backup)?;
+    }
+    Ok(())
+}
+
+fn backup_stamps<P: AsRef<Path>>(db_path: P) -> Result<Vec<(u128, PathBuf)>> {
+    let db_path = db_path.as_ref();
+    let name = match db_path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => format!("{}.", name),
+        None => return Ok(Vec::new()),
+    };
+    let dir = match db_path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+
+    let mut backups = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        let stamp = path
+            .file_name()
+            .and_then(|file_name| file_name.to_str())
+            .and_then(|file_name| file_name.strip_prefix(&name))
+            .and_then(|file_name| file_name.strip_suffix(".bak"))
+            .and_then(|stamp| stamp.parse::<u128>().ok());
+        if let Some(stamp) = stamp {
+            backups.push((stamp, path));
+        }
+    }
+    backups.sort();
+    Ok(backups)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir, remove_file},
+        time::Duration,
+    };
+
+    use crate::{testing::TempDir, BackupPolicy, DumpPolicy, NoDb, SerializationMethod};
+
+    #[test]
+    fn a_chain_of_incremental_backups_is_restored() {
+        let dir = TempDir::new("backup-chain");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Cbor,
+        )
+        .unwrap();
+        db.set("kept", 1).unwrap();
+        db.set("removed", 1).unwrap();
+        db.backup_to(dir.path("full.bak")).unwrap();
+        db.rem("removed").unwrap();
+        db.list_create("list").unwrap();
+        db.list_extend("list", &[1, 2]).unwrap();
+        db.set_add("set", &"a").unwrap();
+        db.backup_incremental(dir.path("inc-1.bak")).unwrap();
+        db.hset("hash", "field", 3).unwrap();
+        db.zadd("zset", &"a", 4.0).unwrap();
+        db.set_with_ttl("session", 5, Duration::from_secs(3600))
+            .unwrap();
+        db.list_add("list", &3).unwrap();
+        db.backup_incremental(dir.path("inc-2.bak")).unwrap();
+
+        let restored = NoDb::restore_backup(
+            dir.path("restored.db"),
+            dir.path("full.bak"),
+            [dir.path("inc-1.bak"), dir.path("inc-2.bak")],
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        assert_eq!(restored.get::<_, i32>("kept"), Some(1));
+        assert!(!restored.exists("removed"));
+        assert_eq!(restored.list_len("list"), 3);
+        assert!(restored.set_contains("set", &"a"));
+        assert_eq!(restored.hget::<i32, _, _>("hash", "field"), Some(3));
+        assert_eq!(restored.zscore("zset", &"a"), Some(4.0));
+        assert!(restored.ttl("session").is_some());
+        assert_eq!(restored.total_keys(), db.total_keys());
+    }
+
+    #[test]
+    fn incremental_backups_must_follow_each_other() {
+        let dir = TempDir::new("backup-order");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        assert!(db.backup_incremental(dir.path("inc-0.bak")).is_err());
+        db.backup_to(dir.path("full.bak")).unwrap();
+        db.set("a", 1).unwrap();
+        db.backup_incremental(dir.path("inc-1.bak")).unwrap();
+        db.set("b", 2).unwrap();
+        db.backup_incremental(dir.path("inc-2.bak")).unwrap();
+
+        for chain in [vec!["inc-2.bak"], vec!["inc-2.bak", "inc-1.bak"]] {
+            let restored = NoDb::restore_backup(
+                dir.path("restored.db"),
+                dir.path("full.bak"),
+                chain.iter().map(|backup| dir.path(backup)),
+                DumpPolicy::Auto,
+                SerializationMethod::Json,
+            );
+            assert!(restored.is_err(), "{:?} was restored", chain);
+        }
+    }
+
+    #[test]
+    fn rotating_backups_are_pruned() {
+        let dir = TempDir::new("backup-rotation");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::OnCall,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.backup_policy = BackupPolicy::EveryDump(2);
+        for i in 0..3 {
+            db.set("a", i).unwrap();
+            db.dump().unwrap();
+        }
+
+        let backups = db.backups().unwrap();
+        assert_eq!(backups.len(), 2);
+        let latest = NoDb::load_read_only(&backups[1], SerializationMethod::Json).unwrap();
+        assert_eq!(latest.get::<_, i32>("a"), Some(2));
+
+        let path = db.rotate_backup(1).unwrap();
+        assert_eq!(db.backups().unwrap(), [path]);
+    }
+
+    #[test]
+    fn no_backup_is_taken_when_the_dump_fails() {
+        let dir = TempDir::new("backup-failed-dump");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        db.backup_policy = BackupPolicy::EveryDump(5);
+        db.set("a", 1).unwrap();
+        db.dump().unwrap();
+
+        // The DB file can't be written once it is a directory.
+        remove_file(&path).unwrap();
+        create_dir(&path).unwrap();
+        db.set("a", 2).unwrap();
+        assert!(db.dump().is_err());
+        assert_eq!(db.backups().unwrap().len(), 1);
+    }
+}
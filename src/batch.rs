@@ -0,0 +1,115 @@
+//! # Batch
+//!
+//! A set of changes accumulated ahead of time and applied to a NoDb instance at once.
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+    ser::{SerializationMethod, SerializeMethod, Serializer},
+    wal::LogOp,
+};
+
+/// A batch of changes to apply to a NoDb instance with
+/// [NoDb::write_batch()](struct.NoDb.html#method.write_batch). Returned in
+/// [NoDb::batch()](struct.NoDb.html#method.batch).
+///
+/// Values are serialized as they are added to the batch, with the serialization method of the DB
+/// the batch was created for.
+pub struct WriteBatch {
+    pub(crate) ser: Serializer,
+    pub(crate) ops: Vec<LogOp>,
+}
+
+impl WriteBatch {
+    pub(crate) fn new(ser_method: SerializationMethod) -> Self {
+        WriteBatch {
+            ser: Serializer::from(ser_method),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Set a key-value pair.
+    ///
+    /// The key has to be a string but the value can be of any type that is serializable.
+    /// If a list is set under this key, it will be overridden by the value.
+    ///
+    /// This method returns an `anyhow::Error` if the value can't be serialized.
+    pub fn set<K: AsRef<str>, V: Serialize>(&mut self, key: K, value: V) -> Result<&mut Self> {
+        let value = self.ser.serialize_data(&value)?;
+        self.ops.push(LogOp::Set {
+            key: key.as_ref().to_string(),
+            value,
+        });
+        Ok(self)
+    }
+
+    /// Remove a key-value pair or a list.
+    pub fn rem<K: AsRef<str>>(&mut self, key: K) -> &mut Self {
+        self.ops.push(LogOp::Rem {
+            key: key.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Create a new empty list.
+    ///
+    /// If another list or value is already set under this key, it will be overridden.
+    pub fn list_create<N: AsRef<str>>(&mut self, name: N) -> &mut Self {
+        self.ops.push(LogOp::ListCreate {
+            name: name.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Add a single item to a list.
+    ///
+    /// The list has to exist in the DB, or be created earlier in the batch, by the time the batch is
+    /// applied. This method returns an `anyhow::Error` if the item can't be serialized.
+    pub fn list_add<N: AsRef<str>, V: Serialize>(
+        &mut self,
+        name: N,
+        value: &V,
+    ) -> Result<&mut Self> {
+        self.list_extend(name, [value])
+    }
+
+    /// Add multiple items to a list.
+    ///
+    /// The list has to exist in the DB, or be created earlier in the batch, by the time the batch is
+    /// applied. This method returns an `anyhow::Error` if one of the items can't be serialized.
+    pub fn list_extend<'a, N, V, I>(&mut self, name: N, seq: I) -> Result<&mut Self>
+    where
+        N: AsRef<str>,
+        V: 'a + Serialize,
+        I: IntoIterator<Item = &'a V>,
+    {
+        let items = seq
+            .into_iter()
+            .map(|v| self.ser.serialize_data(v))
+            .collect::<Result<Vec<_>>>()?;
+        self.ops.push(LogOp::ListExtend {
+            name: name.as_ref().to_string(),
+            items,
+        });
+        Ok(self)
+    }
+
+    /// Remove a list.
+    pub fn list_rm_list<N: AsRef<str>>(&mut self, name: N) -> &mut Self {
+        self.ops.push(LogOp::ListRem {
+            name: name.as_ref().to_string(),
+        });
+        self
+    }
+
+    /// Get the number of changes in the batch.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Check if the batch holds no change.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+}
@@ -15,11 +15,6 @@ const STD: GeneralPurpose = STANDARD;
 pub struct B64;
 
 impl B64 {
-    /// Creates a new `B64` instance.
-    pub const fn new() -> Self {
-        Self {}
-    }
-
     /// Encrypts the given data using the `base64` algorithm.
     pub fn encrypt<T: AsRef<[u8]>>(&self, data: T) -> String {
         STD.encode(data)
@@ -0,0 +1,10 @@
+/// Computes the FNV-1a hash of the given data.
+///
+/// The hash is used to detect changes and corruption of files, not to protect them from tampering.
+pub fn checksum<T: AsRef<[u8]>>(data: T) -> u64 {
+    data.as_ref()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
@@ -1,3 +1,5 @@
 mod b64;
+mod checksum;
 
 pub use b64::B64;
+pub use checksum::checksum;
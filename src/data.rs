@@ -0,0 +1,182 @@
+//! # Data
+//!
+//! The maps holding the data of a NoDb instance, one per kind of collection, along with the time their
+//! keys expire.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use crate::{
+    zset::SortedSet, DbExpiryMap, DbHashMap, DbKeyIndex, DbListMap, DbMap, DbSetMap, DbZSetMap,
+};
+
+/// The data of a DB, e.g. as read from a file.
+#[derive(Default)]
+pub(crate) struct DbData {
+    pub(crate) map: DbMap,
+    pub(crate) list_map: DbListMap,
+    pub(crate) set_map: DbSetMap,
+    pub(crate) hash_map: DbHashMap,
+    pub(crate) zset_map: DbZSetMap,
+    pub(crate) expires: DbExpiryMap,
+}
+
+impl DbData {
+    /// Borrows the maps, so that changes can be applied to them.
+    pub(crate) fn as_mut(&mut self) -> DbDataMut<'_> {
+        DbDataMut {
+            map: &mut self.map,
+            list_map: &mut self.list_map,
+            set_map: &mut self.set_map,
+            hash_map: &mut self.hash_map,
+            zset_map: &mut self.zset_map,
+            expires: &mut self.expires,
+            key_index: None,
+        }
+    }
+
+    /// Moves the data of another DB into this one.
+    pub(crate) fn extend(&mut self, other: DbData) {
+        self.map.extend(other.map);
+        self.list_map.extend(other.list_map);
+        self.set_map.extend(other.set_map);
+        self.hash_map.extend(other.hash_map);
+        self.zset_map.extend(other.zset_map);
+        self.expires.extend(other.expires);
+    }
+
+    /// Returns the keys of every value, list, set, hash and sorted set.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .chain(self.set_map.keys())
+            .chain(self.hash_map.keys())
+            .chain(self.zset_map.keys())
+    }
+}
+
+/// The maps of a DB shared with its snapshots. Sharing the maps is cheap, as they are only copied once
+/// the DB changes them.
+#[derive(Clone)]
+pub(crate) struct SharedData {
+    pub(crate) map: Arc<DbMap>,
+    pub(crate) list_map: Arc<DbListMap>,
+    pub(crate) set_map: Arc<DbSetMap>,
+    pub(crate) hash_map: Arc<DbHashMap>,
+    pub(crate) zset_map: Arc<DbZSetMap>,
+    pub(crate) expires: Arc<DbExpiryMap>,
+}
+
+impl SharedData {
+    /// Returns the keys of every value, list, set, hash and sorted set.
+    pub(crate) fn keys(&self) -> impl Iterator<Item = &String> {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .chain(self.set_map.keys())
+            .chain(self.hash_map.keys())
+            .chain(self.zset_map.keys())
+    }
+}
+
+/// The maps of a DB, borrowed to apply changes to them.
+pub(crate) struct DbDataMut<'a> {
+    pub(crate) map: &'a mut DbMap,
+    pub(crate) list_map: &'a mut DbListMap,
+    pub(crate) set_map: &'a mut DbSetMap,
+    pub(crate) hash_map: &'a mut DbHashMap,
+    pub(crate) zset_map: &'a mut DbZSetMap,
+    pub(crate) expires: &'a mut DbExpiryMap,
+    /// The ordered index of the keys of the values and lists, if the DB keeps one.
+    pub(crate) key_index: Option<&'a mut DbKeyIndex>,
+}
+
+/// A copy of everything held under a key.
+pub(crate) struct KeyData {
+    value: Option<Vec<u8>>,
+    list: Option<Vec<Vec<u8>>>,
+    set: Option<HashSet<Vec<u8>>>,
+    hash: Option<HashMap<String, Vec<u8>>>,
+    zset: Option<SortedSet>,
+    expires: Option<u64>,
+}
+
+impl DbDataMut<'_> {
+    /// Returns `true` if a value, a list, a set, a hash or a sorted set is held under the key.
+    pub(crate) fn contains_key(&self, key: &str) -> bool {
+        self.map.contains_key(key)
+            || self.list_map.contains_key(key)
+            || self.set_map.contains_key(key)
+            || self.hash_map.contains_key(key)
+            || self.zset_map.contains_key(key)
+    }
+
+    /// Sets the value of a key, replacing whatever was held under it and making it persistent.
+    pub(crate) fn set_value(&mut self, key: String, value: Vec<u8>) {
+        self.remove(&key);
+        if let Some(index) = self.key_index.as_mut() {
+            index.insert(key.clone());
+        }
+        self.map.insert(key, value);
+    }
+
+    /// Sets a list, replacing whatever was held under its name and making it persistent.
+    pub(crate) fn set_list(&mut self, name: String, list: Vec<Vec<u8>>) {
+        self.remove(&name);
+        if let Some(index) = self.key_index.as_mut() {
+            index.insert(name.clone());
+        }
+        self.list_map.insert(name, list);
+    }
+
+    /// Removes whatever is held under the key, making it persistent.
+    pub(crate) fn remove(&mut self, key: &str) {
+        if self.map.remove(key).is_some() | self.list_map.remove(key).is_some() {
+            if let Some(index) = self.key_index.as_mut() {
+                index.remove(key);
+            }
+        }
+        self.set_map.remove(key);
+        self.hash_map.remove(key);
+        self.zset_map.remove(key);
+        self.expires.remove(key);
+    }
+
+    /// Copies everything held under the key, so that it can be restored with [put()](#method.put).
+    pub(crate) fn get(&self, key: &str) -> KeyData {
+        KeyData {
+            value: self.map.get(key).cloned(),
+            list: self.list_map.get(key).cloned(),
+            set: self.set_map.get(key).cloned(),
+            hash: self.hash_map.get(key).cloned(),
+            zset: self.zset_map.get(key).cloned(),
+            expires: self.expires.get(key).copied(),
+        }
+    }
+
+    /// Restores what was held under the key.
+    pub(crate) fn put(&mut self, key: &str, data: KeyData) {
+        self.remove(key);
+        if let Some(value) = data.value {
+            self.set_value(key.to_string(), value);
+        }
+        if let Some(list) = data.list {
+            self.set_list(key.to_string(), list);
+        }
+        if let Some(set) = data.set {
+            self.set_map.insert(key.to_string(), set);
+        }
+        if let Some(hash) = data.hash {
+            self.hash_map.insert(key.to_string(), hash);
+        }
+        if let Some(zset) = data.zset {
+            self.zset_map.insert(key.to_string(), zset);
+        }
+        if let Some(at) = data.expires {
+            self.expires.insert(key.to_string(), at);
+        }
+    }
+}
@@ -17,8 +17,7 @@ impl<'a> NoDbExt<'a> {
     /// `#[derive(Serialize, Deserialize)` attribute.
     /// The method returns another `NoDbExt` object that enables to continue adding
     /// items to the list.
-
-    pub fn ladd<V: Serialize>(&mut self, value: V) -> Option<NoDbExt> {
+    pub fn ladd<V: Serialize>(&mut self, value: V) -> Option<NoDbExt<'_>> {
         self.db.list_add(&self.list_name, &value)
     }
 
@@ -33,8 +32,7 @@ impl<'a> NoDbExt<'a> {
     /// of other types as well, as you can see in the example below.
     /// The method returns another `NoDbExt` object that enables to continue adding
     /// items to the list.
-
-    pub fn lextend<'b, V, I>(&mut self, seq: I) -> Option<NoDbExt>
+    pub fn lextend<'b, V, I>(&mut self, seq: I) -> Option<NoDbExt<'_>>
     where
         V: 'b + Serialize,
         I: IntoIterator<Item = &'b V>,
@@ -0,0 +1,263 @@
+//! # Flush
+//!
+//! A background thread that dumps a NoDb instance on schedule, according to its dump policy, even
+//! when no more changes are made to it, and removes its keys as they expire.
+
+use std::{
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::nodb::NoDb;
+
+/// How long to wait before dumping again after a dump failed.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct FlushState {
+    changed: bool,
+    stop: bool,
+    error: Option<Error>,
+}
+
+/// The signal a NoDb instance sends to its flusher when it changes.
+#[derive(Default)]
+pub(crate) struct FlushSignal {
+    state: Mutex<FlushState>,
+    cond: Condvar,
+}
+
+impl FlushSignal {
+    /// Wakes the flusher up so that it schedules the next dump.
+    pub(crate) fn notify(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.changed = true;
+            self.cond.notify_one();
+        }
+    }
+}
+
+/// A background thread dumping a NoDb instance on schedule. Returned in
+/// [NoDbFlusher::spawn()](struct.NoDbFlusher.html#method.spawn).
+///
+/// With [DumpPolicy::Periodic](enum.DumpPolicy.html#variant.Periodic), changes are dumped once the
+/// Duration has passed since the last dump, and with
+/// [DumpPolicy::Debounced](enum.DumpPolicy.html#variant.Debounced), once no change was made for the
+/// Duration. Other dump policies are left alone. Keys are also removed from the DB as they expire, see
+/// [NoDb::purge_expired()](struct.NoDb.html#method.purge_expired). The thread stops when the flusher is
+/// dropped.
+pub struct NoDbFlusher {
+    signal: Arc<FlushSignal>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl NoDbFlusher {
+    /// Spawn a flusher for a NoDb instance shared between threads.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::{Arc, Mutex}, time::Duration};
+    /// use nodb::{NoDb, NoDbFlusher, DumpPolicy, SerializationMethod};
+    ///
+    /// let policy = DumpPolicy::Debounced(Duration::from_millis(500));
+    /// let db = NoDb::new("example.db", policy, SerializationMethod::Json).unwrap();
+    /// let db = Arc::new(Mutex::new(db));
+    /// let flusher = NoDbFlusher::spawn(&db);
+    ///
+    /// db.lock().unwrap().set("key", 100).unwrap();
+    /// // ... the change is dumped after 500ms without further changes.
+    ///
+    /// flusher.stop().unwrap();
+    /// ```
+    pub fn spawn(db: &Arc<Mutex<NoDb>>) -> Self {
+        let signal = Arc::new(FlushSignal::default());
+        if let Ok(mut db) = db.lock() {
+            db.flush_signal = Some(signal.clone());
+        }
+
+        let thread_db = db.clone();
+        let thread_signal = signal.clone();
+        let handle = thread::spawn(move || run(thread_db, thread_signal));
+        NoDbFlusher {
+            signal,
+            handle: Some(handle),
+        }
+    }
+
+    /// Stop the flusher.
+    ///
+    /// This method returns the last error that happened while dumping the DB or removing its expired keys
+    /// in the background, if any.
+    pub fn stop(mut self) -> Result<()> {
+        self.shutdown();
+        let mut state = self
+            .signal
+            .state
+            .lock()
+            .map_err(|_| anyhow!("Flusher state is poisoned"))?;
+        match state.error.take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+
+    fn shutdown(&mut self) {
+        if let Ok(mut state) = self.signal.state.lock() {
+            state.stop = true;
+            self.signal.cond.notify_one();
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for NoDbFlusher {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+fn run(db: Arc<Mutex<NoDb>>, signal: Arc<FlushSignal>) {
+    loop {
+        let due_in = match db.lock() {
+            Ok(mut db) => match step(&mut db) {
+                Ok(due_in) => due_in,
+                Err(err) => {
+                    drop(db);
+                    if let Ok(mut state) = signal.state.lock() {
+                        state.error = Some(err);
+                    }
+                    Some(RETRY_DELAY)
+                }
+            },
+            Err(_) => return,
+        };
+
+        let mut state = match signal.state.lock() {
+            Ok(state) => state,
+            Err(_) => return,
+        };
+        if !state.changed && !state.stop {
+            state = match due_in {
+                Some(due_in) => match signal.cond.wait_timeout(state, due_in) {
+                    Ok((state, _)) => state,
+                    Err(_) => return,
+                },
+                None => match signal.cond.wait(state) {
+                    Ok(state) => state,
+                    Err(_) => return,
+                },
+            };
+        }
+        if state.stop {
+            return;
+        }
+        state.changed = false;
+    }
+}
+
+/// Dumps the DB and removes its expired keys if they are due, and returns how long until they are
+/// due next, or `None` if they aren't.
+fn step(db: &mut NoDb) -> Result<Option<Duration>> {
+    if db.purge_due_in().is_some_and(|due_in| due_in.is_zero()) {
+        db.purge_expired()?;
+    }
+    if db.flush_due_in().is_some_and(|due_in| due_in.is_zero()) {
+        db.dump()?;
+    }
+    Ok(match (db.flush_due_in(), db.purge_due_in()) {
+        (Some(flush), Some(purge)) => Some(flush.min(purge)),
+        (flush, purge) => flush.or(purge),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::create_dir,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::NoDbFlusher;
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    /// Waits for a condition to hold, giving up after long enough for a loaded machine.
+    fn wait_for<F: Fn() -> bool>(condition: F) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_secs(30) {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn changes_are_dumped_on_schedule() {
+        for policy in [
+            DumpPolicy::Periodic(Duration::from_millis(50)),
+            DumpPolicy::Debounced(Duration::from_millis(50)),
+        ] {
+            let dir = TempDir::new("flush-schedule");
+            let path = dir.path("test.db");
+            let db = Arc::new(Mutex::new(
+                NoDb::new(&path, policy, SerializationMethod::Json).unwrap(),
+            ));
+            let flusher = NoDbFlusher::spawn(&db);
+            db.lock().unwrap().set("key", 1).unwrap();
+
+            assert!(wait_for(|| !db.lock().unwrap().is_dirty()), "{:?}", policy);
+            flusher.stop().unwrap();
+            drop(db);
+            let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+            assert_eq!(db.get::<_, i32>("key"), Some(1));
+        }
+    }
+
+    #[test]
+    fn expired_keys_are_purged() {
+        let dir = TempDir::new("flush-purge");
+        let db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        );
+        let db = Arc::new(Mutex::new(db.unwrap()));
+        let flusher = NoDbFlusher::spawn(&db);
+        // The key expires right away, as the clock of the flusher's thread can't be moved forward.
+        db.lock()
+            .unwrap()
+            .set_with_ttl("key", 1, Duration::ZERO)
+            .unwrap();
+
+        assert!(wait_for(|| db.lock().unwrap().map.is_empty()));
+        flusher.stop().unwrap();
+    }
+
+    #[test]
+    fn stop_returns_the_last_error() {
+        let dir = TempDir::new("flush-error");
+        let path = dir.path("test.db");
+        let policy = DumpPolicy::Debounced(Duration::from_millis(10));
+        let db = Arc::new(Mutex::new(
+            NoDb::new(&path, policy, SerializationMethod::Json).unwrap(),
+        ));
+        // The DB file can't be written once it is a directory.
+        create_dir(&path).unwrap();
+        let flusher = NoDbFlusher::spawn(&db);
+        db.lock().unwrap().set("key", 1).unwrap();
+
+        let failed = || flusher.signal.state.lock().unwrap().error.is_some();
+        assert!(wait_for(failed));
+        assert!(flusher.stop().is_err());
+        assert!(db.lock().unwrap().is_dirty());
+    }
+}
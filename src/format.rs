@@ -0,0 +1,375 @@
+//! # Format
+//!
+//! The layout of NoDb files.
+//!
+//! Every file starts with a header made of the magic bytes `NODB`, the version of the file format,
+//! the serialization method of the data and a set of flags describing how the data is encoded.
+//!
+//! The header is followed by a line holding the checksum of the rest of the file and the number of
+//! entries in it, then by one line per entry. Each entry holds the data of a single key and is made of:
+//! - the checksum of the rest of the line, so that corrupted entries can be told apart from intact ones,
+//! - the kind of data held by the key (`value`, `list`, `set`, `hash` or `zset`), followed by a colon,
+//! - the data, serialized with the serialization method of the DB,
+//! - optionally, after a space, the time the key expires in milliseconds since the Unix epoch.
+//!
+//! New kinds of data can be added without changing the version of the format: entries of a kind
+//! unknown to a version of NoDb are reported as unreadable by it.
+//!
+//! Files written before the header was introduced have no header, and hold all the values and lists in
+//! a single base64 encoded entry without checksum.
+
+use std::str::from_utf8;
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    crypto::{checksum, B64},
+    ser::SerializationMethod,
+};
+
+const MAGIC: &[u8; 4] = b"NODB";
+const HEADER_LEN: usize = MAGIC.len() + 3;
+const METHOD_COUNT: u8 = 8;
+
+/// The version of the file format, written by this version of NoDb.
+pub(crate) const FORMAT_VERSION: u8 = 1;
+
+/// The data is base64 encoded.
+const FLAG_BASE64: u8 = 0b0000_0001;
+/// Every flag known to this version of NoDb.
+const KNOWN_FLAGS: u8 = FLAG_BASE64;
+
+/// The kind of data held by an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    /// A single value.
+    Value,
+    /// A single list.
+    List,
+    /// A single set.
+    Set,
+    /// A single hash.
+    Hash,
+    /// A single sorted set.
+    ZSet,
+    /// All the values and lists of a file written before the header was introduced, serialized
+    /// together by the serialization method of the DB.
+    Legacy,
+}
+
+impl EntryKind {
+    /// Returns the tag written before the data of entries of this kind.
+    fn tag(self) -> &'static str {
+        match self {
+            EntryKind::Value => "value",
+            EntryKind::List => "list",
+            EntryKind::Set => "set",
+            EntryKind::Hash => "hash",
+            EntryKind::ZSet => "zset",
+            EntryKind::Legacy => "",
+        }
+    }
+
+    /// Returns the kind of entries whose data follows the tag, or `None` if the tag is unknown.
+    fn from_tag(tag: &[u8]) -> Option<Self> {
+        [
+            EntryKind::Value,
+            EntryKind::List,
+            EntryKind::Set,
+            EntryKind::Hash,
+            EntryKind::ZSet,
+        ]
+        .into_iter()
+        .find(|kind| kind.tag().as_bytes() == tag)
+    }
+}
+
+/// An entry read from a file.
+pub(crate) struct Entry {
+    /// The kind of data held by the entry, or `None` if it is unknown.
+    pub(crate) kind: Option<EntryKind>,
+    /// The serialized data of the entry, or `None` if it couldn't be decoded.
+    pub(crate) data: Option<Vec<u8>>,
+    /// Whether the checksum of the entry matches its data.
+    pub(crate) intact: bool,
+    /// The time the key of the entry expires, if it does.
+    pub(crate) expires: Option<u64>,
+}
+
+/// The content of a file.
+pub(crate) struct Payload {
+    pub(crate) method: SerializationMethod,
+    pub(crate) entries: Vec<Entry>,
+    /// The number of entries missing from the end of a truncated file.
+    pub(crate) missing: usize,
+    /// Whether the checksum of the file matches its content.
+    pub(crate) intact: bool,
+}
+
+/// Prepends the header to the serialized entries, along with their kind and the time they expire,
+/// and encodes them.
+pub(crate) fn encode(
+    method: SerializationMethod,
+    entries: &[(EntryKind, Vec<u8>, Option<u64>)],
+) -> Vec<u8> {
+    let mut body = String::new();
+    for (kind, entry, expires) in entries {
+        let mut data = format!("{}:{}", kind.tag(), B64.encrypt(entry));
+        if let Some(expires) = expires {
+            data.push_str(&format!(" {}", expires));
+        }
+        body.push_str(&format!("{:016x} {}\n", checksum(data.as_bytes()), data));
+    }
+
+    let mut content = Vec::with_capacity(HEADER_LEN + body.len() + 24);
+    content.extend_from_slice(MAGIC);
+    content.extend_from_slice(&[FORMAT_VERSION, method as u8, FLAG_BASE64]);
+    content.extend_from_slice(
+        format!("{:016x} {}\n", checksum(body.as_bytes()), entries.len()).as_bytes(),
+    );
+    content.extend_from_slice(body.as_bytes());
+    content
+}
+
+/// Reads the header of the content and decodes its entries.
+///
+/// The serialization method recorded in the header is returned, or `fallback` if the content
+/// has no header.
+pub(crate) fn decode(content: &[u8], fallback: SerializationMethod) -> Result<Payload> {
+    match content.strip_prefix(MAGIC) {
+        Some(rest) => {
+            let (method, flags, data) = parse(rest)?;
+            decode_entries(method, data, flags & FLAG_BASE64 != 0)
+        }
+        None => {
+            let data = B64.decrypt(content).ok();
+            Ok(Payload {
+                method: fallback,
+                intact: data.is_some(),
+                entries: vec![Entry {
+                    kind: Some(EntryKind::Legacy),
+                    intact: data.is_some(),
+                    data,
+                    expires: None,
+                }],
+                missing: 0,
+            })
+        }
+    }
+}
+
+/// Returns `true` if the content starts with a header.
+pub(crate) fn has_header(content: &[u8]) -> bool {
+    content.starts_with(MAGIC)
+}
+
+fn parse(rest: &[u8]) -> Result<(SerializationMethod, u8, &[u8])> {
+    match rest {
+        [version, method, flags, data @ ..] => {
+            if *version != FORMAT_VERSION {
+                return Err(anyhow!(
+                    "Unsupported file format version {} (expected {})",
+                    version,
+                    FORMAT_VERSION
+                ));
+            }
+            if *method >= METHOD_COUNT {
+                return Err(anyhow!("Unknown serialization method {} in header", method));
+            }
+            if flags & !KNOWN_FLAGS != 0 {
+                return Err(anyhow!("Unsupported flags {:#010b} in header", flags));
+            }
+            Ok((SerializationMethod::from(*method), *flags, data))
+        }
+        _ => Err(anyhow!("Truncated file header")),
+    }
+}
+
+fn decode_data(data: &[u8], base64: bool) -> Option<Vec<u8>> {
+    if base64 {
+        B64.decrypt(data).ok()
+    } else {
+        Some(data.to_vec())
+    }
+}
+
+fn decode_entries(method: SerializationMethod, data: &[u8], base64: bool) -> Result<Payload> {
+    let (summary, body) = match data.iter().position(|byte| *byte == b'\n') {
+        Some(pos) => (&data[..pos], &data[pos + 1..]),
+        None => return Err(anyhow!("Truncated file header")),
+    };
+    let (sum, count) =
+        split_at_byte(summary, b' ').ok_or_else(|| anyhow!("Corrupted file header"))?;
+    let count = from_utf8(count)
+        .ok()
+        .and_then(|count| count.parse::<usize>().ok())
+        .ok_or_else(|| anyhow!("Corrupted file header"))?;
+
+    let entries = body
+        .split_inclusive(|byte| *byte == b'\n')
+        .map(|line| decode_entry(line, base64))
+        .collect::<Vec<_>>();
+
+    Ok(Payload {
+        method,
+        intact: parse_checksum(sum) == Some(checksum(body)) && entries.len() == count,
+        missing: count.saturating_sub(entries.len()),
+        entries,
+    })
+}
+
+fn decode_entry(line: &[u8], base64: bool) -> Entry {
+    let entry = line
+        .strip_suffix(b"\n")
+        .and_then(|line| split_at_byte(line, b' '))
+        .and_then(|(sum, rest)| {
+            let intact = parse_checksum(sum) == Some(checksum(rest));
+            let (tag, rest) = split_at_byte(rest, b':')?;
+            Some((intact, EntryKind::from_tag(tag), rest))
+        });
+    let (intact, kind, rest) = match entry {
+        Some(entry) => entry,
+        None => {
+            return Entry {
+                kind: None,
+                data: None,
+                intact: false,
+                expires: None,
+            }
+        }
+    };
+
+    match split_at_byte(rest, b' ') {
+        Some((data, expires)) => {
+            let expires = from_utf8(expires)
+                .ok()
+                .and_then(|expires| expires.parse::<u64>().ok());
+            Entry {
+                kind,
+                intact: intact && expires.is_some(),
+                data: decode_data(data, base64),
+                expires,
+            }
+        }
+        None => Entry {
+            kind,
+            intact,
+            data: decode_data(rest, base64),
+            expires: None,
+        },
+    }
+}
+
+/// Splits a line at the first occurrence of a separator, e.g. into its checksum and the rest of it.
+fn split_at_byte(line: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let pos = line.iter().position(|byte| *byte == separator)?;
+    Some((&line[..pos], &line[pos + 1..]))
+}
+
+fn parse_checksum(sum: &[u8]) -> Option<u64> {
+    u64::from_str_radix(from_utf8(sum).ok()?, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{read, write};
+
+    use super::*;
+    use crate::{testing::TempDir, DumpPolicy, NoDb};
+
+    /// Encodes a file holding entries with raw tags, as a newer version of NoDb could write them.
+    fn encode_tagged(entries: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = String::new();
+        for (tag, entry) in entries {
+            let data = format!("{}:{}", tag, B64.encrypt(entry));
+            body.push_str(&format!("{:016x} {}\n", checksum(data.as_bytes()), data));
+        }
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(&[FORMAT_VERSION, SerializationMethod::Json as u8, FLAG_BASE64]);
+        content.extend_from_slice(
+            format!("{:016x} {}\n", checksum(body.as_bytes()), entries.len()).as_bytes(),
+        );
+        content.extend_from_slice(body.as_bytes());
+        content
+    }
+
+    #[test]
+    fn entries_round_trip() {
+        let entries = vec![
+            (EntryKind::Value, b"value".to_vec(), None),
+            (EntryKind::List, b"list".to_vec(), Some(42)),
+            (EntryKind::ZSet, Vec::new(), None),
+        ];
+        let content = encode(SerializationMethod::Ron, &entries);
+        assert!(has_header(&content));
+
+        let payload = decode(&content, SerializationMethod::Json).unwrap();
+        assert_eq!(payload.method, SerializationMethod::Ron);
+        assert!(payload.intact);
+        assert_eq!(payload.missing, 0);
+        let decoded = payload
+            .entries
+            .into_iter()
+            .map(|entry| {
+                assert!(entry.intact);
+                (entry.kind.unwrap(), entry.data.unwrap(), entry.expires)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(decoded, entries);
+    }
+
+    #[test]
+    fn the_method_is_detected_from_the_header() {
+        let dir = TempDir::new("format-open");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Bin).unwrap();
+        db.set("key", 1).unwrap();
+        drop(db);
+
+        let db = NoDb::open(&path, DumpPolicy::Never).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+        drop(db);
+        write(&path, B64.encrypt(r#"[{"key":"1"},{}]"#)).unwrap();
+        assert!(NoDb::open(&path, DumpPolicy::Never).is_err());
+    }
+
+    #[test]
+    fn unsupported_headers_are_rejected() {
+        let dir = TempDir::new("format-header");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("key", 1).unwrap();
+        drop(db);
+        let content = read(&path).unwrap();
+
+        for (pos, byte) in [(4, FORMAT_VERSION + 1), (5, METHOD_COUNT), (6, 0b1000_0000)] {
+            let mut content = content.clone();
+            content[pos] = byte;
+            assert!(decode(&content, SerializationMethod::Json).is_err());
+            write(&path, &content).unwrap();
+            assert!(NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).is_err());
+        }
+        assert!(decode(&content[..HEADER_LEN - 1], SerializationMethod::Json).is_err());
+    }
+
+    #[test]
+    fn entries_of_unknown_kinds_are_unreadable() {
+        let dir = TempDir::new("format-unknown-kind");
+        let path = dir.path("test.db");
+        write(
+            &path,
+            encode_tagged(&[
+                ("value", br#"{"key":"a","value":[49]}"#),
+                ("future", b"data"),
+            ]),
+        )
+        .unwrap();
+
+        assert!(NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).is_err());
+        let (db, report) =
+            NoDb::load_salvage(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("a"), Some(1));
+        assert_eq!(report.recovered, 1);
+        assert_eq!(report.unreadable_entries, 1);
+    }
+}
@@ -0,0 +1,134 @@
+//! # Glob
+//!
+//! Matching keys against Redis-style glob patterns:
+//! - `*` matches any sequence of characters, including an empty one.
+//! - `?` matches any single character.
+//! - `[abc]` matches one of the characters between the brackets, `[a-z]` one of a range of characters,
+//!   and `[^abc]` any character but the ones between the brackets.
+//! - `\` escapes the character that follows it, e.g. `\*` matches a `*`.
+
+/// Returns `true` if the key matches the pattern.
+pub(crate) fn matches(pattern: &str, key: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let key = key.chars().collect::<Vec<_>>();
+    let (mut p, mut k) = (0, 0);
+    // The position in the pattern after the last `*`, and in the key where it stopped matching.
+    let mut star = None;
+    while k < key.len() {
+        if pattern.get(p) == Some(&'*') {
+            p += 1;
+            star = Some((p, k));
+            continue;
+        }
+        if let Some(next) = match_char(&pattern, p, key[k]) {
+            p = next;
+            k += 1;
+            continue;
+        }
+        // Let the last `*` match one more character and try again from there.
+        match star {
+            Some((star_p, star_k)) => {
+                p = star_p;
+                k = star_k + 1;
+                star = Some((star_p, k));
+            }
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Returns the part of the pattern before its first special character, that every matching key starts
+/// with.
+pub(crate) fn literal_prefix(pattern: &str) -> &str {
+    let end = pattern.find(['*', '?', '[', '\\']).unwrap_or(pattern.len());
+    &pattern[..end]
+}
+
+/// Matches a character of the key against the element of the pattern at position `p`, and returns the
+/// position of the next element if it matches.
+fn match_char(pattern: &[char], p: usize, c: char) -> Option<usize> {
+    match *pattern.get(p)? {
+        '?' => Some(p + 1),
+        '\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        '[' => {
+            let mut i = p + 1;
+            let negated = pattern.get(i) == Some(&'^');
+            if negated {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != ']' {
+                if pattern[i] == '\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+                    let (start, end) = (pattern[i], pattern[i + 2]);
+                    matched |= (start.min(end)..=start.max(end)).contains(&c);
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            // An unterminated class extends to the end of the pattern.
+            (matched != negated).then_some((i + 1).min(pattern.len()))
+        }
+        literal => (literal == c).then_some(p + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{literal_prefix, matches};
+
+    #[test]
+    fn wildcards_match_any_characters() {
+        assert!(matches("user:*", "user:"));
+        assert!(matches("user:*", "user:42"));
+        assert!(!matches("user:*", "users"));
+        assert!(matches("*:name", "user:42:name"));
+        assert!(matches("a*b*c", "aXbYbc"));
+        assert!(!matches("a*b*c", "aXbY"));
+        assert!(matches("user:?", "user:4"));
+        assert!(!matches("user:?", "user:42"));
+        assert!(!matches("user:?", "user:"));
+        assert!(matches("**", ""));
+        assert!(matches("ü?", "üß"));
+    }
+
+    #[test]
+    fn classes_match_one_of_their_characters() {
+        assert!(matches("h[ae]llo", "hello"));
+        assert!(matches("h[ae]llo", "hallo"));
+        assert!(!matches("h[ae]llo", "hillo"));
+        assert!(matches("key[0-9]", "key7"));
+        assert!(matches("key[9-0]", "key7"));
+        assert!(!matches("key[0-9]", "keyx"));
+        assert!(matches("h[^e]llo", "hallo"));
+        assert!(!matches("h[^e]llo", "hello"));
+        // A `-` at the end of a class is a literal.
+        assert!(matches("a[x-]", "a-"));
+        assert!(matches("a[\\]]", "a]"));
+        assert!(matches("a[bc", "ab"));
+    }
+
+    #[test]
+    fn escaped_characters_match_themselves() {
+        assert!(matches("a\\*", "a*"));
+        assert!(!matches("a\\*", "ab"));
+        assert!(matches("a\\?b", "a?b"));
+        assert!(matches("a\\[b", "a[b"));
+        assert!(matches("a\\\\", "a\\"));
+    }
+
+    #[test]
+    fn the_literal_prefix_stops_at_the_first_special_character() {
+        assert_eq!(literal_prefix("user:*"), "user:");
+        assert_eq!(literal_prefix("user:4?"), "user:4");
+        assert_eq!(literal_prefix("key[0-9]"), "key");
+        assert_eq!(literal_prefix("a\\*"), "a");
+        assert_eq!(literal_prefix("plain"), "plain");
+        assert_eq!(literal_prefix("*"), "");
+    }
+}
@@ -1,19 +1,63 @@
-use std::{collections::hash_map::Iter as HashMapIter, slice::Iter as SliceIter};
+use std::{
+    collections::{btree_set::Range as BTreeSetRange, hash_map::Iter as HashMapIter},
+    slice::Iter as SliceIter,
+    vec::IntoIter as VecIntoIter,
+};
 
 use serde::de::DeserializeOwned;
 
-use crate::ser::{SerializeMethod, Serializer};
+use crate::{
+    ser::{SerializeMethod, Serializer},
+    ttl::is_expired,
+    DbExpiryMap, DbMap,
+};
 
-/// Iterator object for iterating over keys and values in NoDb. Returned in [NoDb::iter()](struct.NoDb.html#method.iter)
+/// The keys and values iterated over by a [NoDbIter].
+pub(crate) enum MapIter<'a> {
+    /// Every key, in no particular order.
+    Unordered(HashMapIter<'a, String, Vec<u8>>),
+    /// The keys of an index of the map within a range, in key order or in reverse order.
+    Indexed {
+        keys: BTreeSetRange<'a, String>,
+        map: &'a DbMap,
+        rev: bool,
+    },
+    /// Keys that were sorted beforehand.
+    Sorted(VecIntoIter<(&'a String, &'a Vec<u8>)>),
+}
+
+impl<'a> Iterator for MapIter<'a> {
+    type Item = (&'a String, &'a Vec<u8>);
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            MapIter::Unordered(iter) => iter.next(),
+            MapIter::Indexed { keys, map, rev } => loop {
+                let key = if *rev { keys.next_back() } else { keys.next() }?;
+                if let Some(value) = map.get(key) {
+                    return Some((key, value));
+                }
+            },
+            MapIter::Sorted(iter) => iter.next(),
+        }
+    }
+}
+
+/// Iterator object for iterating over keys and values in NoDb. Returned in [NoDb::iter()](struct.NoDb.html#method.iter),
+/// [NoDb::range()](struct.NoDb.html#method.range) and [NoDb::range_rev()](struct.NoDb.html#method.range_rev)
 pub struct NoDbIter<'a> {
-    pub(crate) map_iter: HashMapIter<'a, String, Vec<u8>>,
+    pub(crate) map_iter: MapIter<'a>,
     pub(crate) ser: &'a Serializer,
+    pub(crate) expires: &'a DbExpiryMap,
+    pub(crate) now: u64,
 }
 
 impl<'a> Iterator for NoDbIter<'a> {
     type Item = NoDbIterItem<'a>;
     fn next(&mut self) -> Option<Self::Item> {
-        match self.map_iter.next() {
+        match self
+            .map_iter
+            .find(|(k, _)| !is_expired(self.expires, k, self.now))
+        {
             Some((k, v)) => Some(NoDbIterItem {
                 key: k,
                 val: v,
@@ -33,7 +77,6 @@ pub struct NoDbIterItem<'a> {
 
 impl<'a> NoDbIterItem<'a> {
     /// Get the key
-
     pub fn get_key(&self) -> &str {
         self.key
     }
@@ -47,7 +90,6 @@ impl<'a> NoDbIterItem<'a> {
     /// Since the values are stored in a serialized way the returned object is
     /// not a reference to the value stored in a DB but actually a new instance of it.
     /// The method returns `Some(V)` if deserialization succeeds or `None` otherwise.
-
     pub fn get_value<V>(&self) -> Option<V>
     where
         V: DeserializeOwned,
@@ -94,3 +136,68 @@ impl<'a> NoDbListIterItem<'a> {
         self.ser.deserialize_data(self.val)
     }
 }
+
+/// Iterator object for scanning the values and lists of NoDb whose keys match a prefix or a pattern, in key
+/// order. Returned in [NoDb::scan_prefix()](struct.NoDb.html#method.scan_prefix) and
+/// [NoDb::keys_matching()](struct.NoDb.html#method.keys_matching)
+pub struct NoDbScanIter<'a> {
+    pub(crate) items: Box<dyn Iterator<Item = NoDbScanItem<'a>> + 'a>,
+}
+
+impl<'a> Iterator for NoDbScanIter<'a> {
+    type Item = NoDbScanItem<'a>;
+    fn next(&mut self) -> Option<Self::Item> {
+        self.items.next()
+    }
+}
+
+/// What is held under a key found by a scan.
+pub(crate) enum ScanData<'a> {
+    Value(&'a Vec<u8>),
+    List(&'a Vec<Vec<u8>>),
+}
+
+/// The object returned in each iteration when scanning the values and lists of NoDb
+pub struct NoDbScanItem<'a> {
+    pub(crate) key: &'a str,
+    pub(crate) data: ScanData<'a>,
+    pub(crate) ser: &'a Serializer,
+}
+
+impl<'a> NoDbScanItem<'a> {
+    /// Get the key
+    pub fn get_key(&self) -> &str {
+        self.key
+    }
+
+    /// Check if the key holds a list rather than a value.
+    pub fn is_list(&self) -> bool {
+        matches!(self.data, ScanData::List(_))
+    }
+
+    /// Get the value of the key.
+    ///
+    /// It's the user's responsibility to know the value type and give it while calling this method.
+    /// The method returns `Some(V)` if the key holds a value and deserialization succeeds, or `None`
+    /// otherwise, e.g. if the key holds a list.
+    pub fn get_value<V: DeserializeOwned>(&self) -> Option<V> {
+        match self.data {
+            ScanData::Value(value) => self.ser.deserialize_data(value),
+            ScanData::List(_) => None,
+        }
+    }
+
+    /// Return an iterator over the items of the list held by the key.
+    ///
+    /// If the key holds a value rather than a list, the iterator is empty.
+    pub fn list_iter(&self) -> NoDbListIter<'a> {
+        let list = match self.data {
+            ScanData::Value(_) => &[][..],
+            ScanData::List(list) => list.as_slice(),
+        };
+        NoDbListIter {
+            list_iter: list.iter(),
+            ser: self.ser,
+        }
+    }
+}
@@ -11,25 +11,52 @@
 //! - **Serialization**: NoDb supports different serialization methods with Serde.
 
 pub use anyhow::Result;
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use zset::SortedSet;
 
 type DbMap = HashMap<String, Vec<u8>>;
 type DbListMap = HashMap<String, Vec<Vec<u8>>>;
+type DbSetMap = HashMap<String, HashSet<Vec<u8>>>;
+type DbHashMap = HashMap<String, HashMap<String, Vec<u8>>>;
+type DbZSetMap = HashMap<String, SortedSet>;
+type DbExpiryMap = HashMap<String, u64>;
+type DbKeyIndex = BTreeSet<String>;
 
 pub use self::{
+    batch::WriteBatch,
     ext::NoDbExt,
-    iter::{NoDbIter, NoDbIterItem, NoDbListIter, NoDbListIterItem},
-    nodb::{DumpPolicy, NoDb},
+    flush::NoDbFlusher,
+    iter::{NoDbIter, NoDbIterItem, NoDbListIter, NoDbListIterItem, NoDbScanItem, NoDbScanIter},
+    nodb::{BackupPolicy, DropPolicy, DumpPolicy, Durability, NoDb},
+    salvage::SalvageReport,
     ser::SerializationMethod,
+    snapshot::NoDbSnapshot,
+    txn::Transaction,
 };
 
 pub mod prelude {
     pub use crate::{NoDb, NoDbExt, SerializationMethod};
 }
 
+mod backup;
+mod batch;
 mod crypto;
+mod data;
 mod ext;
+mod flush;
+mod format;
+mod glob;
 mod iter;
+mod lock;
 mod nodb;
 mod query;
+mod salvage;
 mod ser;
+mod snapshot;
+#[cfg(test)]
+mod testing;
+mod tmp;
+mod ttl;
+mod txn;
+mod wal;
+mod zset;
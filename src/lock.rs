@@ -0,0 +1,125 @@
+//! # Lock
+//!
+//! Advisory locking of NoDb files, so that two processes don't write the same DB.
+//!
+//! The lock is taken on a side file (`<db>.lock`) rather than on the DB file itself, since the DB file
+//! is replaced on every dump. The lock is released when the file is closed, i.e. when the NoDb instance
+//! holding it is dropped or the process exits. The side file itself is never removed, as an instance
+//! could have opened it and be about to lock it.
+
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+
+/// A lock held on a NoDb file.
+pub(crate) struct DbLock {
+    _file: File,
+    shared: bool,
+}
+
+impl DbLock {
+    /// Takes the exclusive lock of a DB that is written to.
+    pub(crate) fn exclusive<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        let file = open(&db_path)?;
+        match file.try_lock() {
+            Ok(_) => Ok(DbLock {
+                _file: file,
+                shared: false,
+            }),
+            Err(TryLockError::WouldBlock) => Err(anyhow!(
+                "{} is locked by another NoDb instance",
+                db_path.as_ref().display()
+            )),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    /// Takes a shared lock of a DB that is only read from.
+    pub(crate) fn shared<P: AsRef<Path>>(db_path: P) -> Result<Self> {
+        let file = open(&db_path)?;
+        match file.try_lock_shared() {
+            Ok(_) => Ok(DbLock {
+                _file: file,
+                shared: true,
+            }),
+            Err(TryLockError::WouldBlock) => Err(anyhow!(
+                "{} is locked for writing by another NoDb instance",
+                db_path.as_ref().display()
+            )),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    /// Returns `true` if the DB is only read from.
+    pub(crate) fn is_shared(&self) -> bool {
+        self.shared
+    }
+}
+
+fn open<P: AsRef<Path>>(db_path: P) -> Result<File> {
+    let mut path = OsString::from(db_path.as_ref().as_os_str());
+    path.push(".lock");
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(PathBuf::from(path))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    #[test]
+    fn a_second_instance_is_refused() {
+        let dir = TempDir::new("lock-second");
+        let path = dir.path("test.db");
+        let _db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+
+        assert!(NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).is_err());
+        assert!(NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).is_err());
+        assert!(NoDb::load_read_only(&path, SerializationMethod::Json).is_err());
+    }
+
+    #[test]
+    fn the_lock_is_released_on_drop() {
+        let dir = TempDir::new("lock-drop");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("key", 1).unwrap();
+        drop(db);
+
+        let db = NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+        db.close().unwrap();
+        assert!(NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).is_ok());
+    }
+
+    #[test]
+    fn read_only_instances_share_the_db() {
+        let dir = TempDir::new("lock-read-only");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("key", 1).unwrap();
+        drop(db);
+
+        let mut reader = NoDb::load_read_only(&path, SerializationMethod::Json).unwrap();
+        let other = NoDb::load_read_only(&path, SerializationMethod::Json).unwrap();
+        assert_eq!(reader.get::<_, i32>("key"), Some(1));
+        assert_eq!(other.get::<_, i32>("key"), Some(1));
+        assert!(NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).is_err());
+
+        // Changes stay in memory, and can't be dumped.
+        reader.set("key", 2).unwrap();
+        assert!(reader.dump().is_err());
+        drop((reader, other));
+
+        let db = NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+    }
+}
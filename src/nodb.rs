@@ -3,20 +3,40 @@
 //! - An data structure representing a NoDB instance.
 
 use std::{
-    fs::{read, rename, write, DirBuilder},
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter, Result as FmtResult},
+    fs::{read, remove_file, rename, DirBuilder, File},
+    io::Write,
+    ops::{Bound, RangeBounds},
     path::{Path, PathBuf},
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
 };
 
 use anyhow::{anyhow, Result};
 use serde::{de::DeserializeOwned, Serialize};
 
 use crate::{
-    crypto::B64,
+    backup::{apply_incremental, prune_backups, rotated_backups, rotated_path, BackupState},
+    batch::WriteBatch,
+    crypto::checksum,
+    data::{DbDataMut, SharedData},
     ext::NoDbExt,
-    iter::{NoDbIter, NoDbListIter},
+    flush::FlushSignal,
+    format::{self, EntryKind},
+    glob,
+    iter::{MapIter, NoDbIter, NoDbListIter, NoDbScanItem, NoDbScanIter, ScanData},
+    lock::DbLock,
+    salvage::{read_entries, SalvageReport},
     ser::{SerializationMethod, SerializeMethod, Serializer},
-    DbListMap, DbMap,
+    snapshot::NoDbSnapshot,
+    tmp::{sweep, temp_files, temp_path},
+    ttl::{self, expires_at, is_expired, is_expired_now, next_expiry},
+    txn::Transaction,
+    wal::{LogOp, Wal},
+    zset::SortedSet,
+    DbExpiryMap, DbHashMap, DbKeyIndex, DbListMap, DbMap, DbSetMap, DbZSetMap,
 };
 
 /// An enum that determines the policy of dumping NoDb changes into the file
@@ -24,7 +44,9 @@ use crate::{
 pub enum DumpPolicy {
     /// Never dump the changes into the file
     Never,
-    /// Every change will be dumped immediately and automatically to the file
+    /// Every change will be dumped immediately and automatically to the file.
+    /// Changes are appended to a write-ahead log next to the file, which is folded back into
+    /// the file by a full dump once it grows as large as the file itself.
     Auto,
     #[default]
     /// Data won't be dumped unless the developer calls [NoDb::dump()](struct.NoDb.html#method.dump) proactively to dump the data
@@ -33,55 +55,213 @@ pub enum DumpPolicy {
     /// The way this mechanism works is as follows: each time there is a DB change the last DB dump time is checked.
     /// If the time that has passed since the last dump is higher than Duration, changes will be dumped,
     /// otherwise changes will not be dumped.
+    /// A [NoDbFlusher](struct.NoDbFlusher.html) can also be spawned to dump the changes on schedule
+    /// even when no more changes are made.
     Periodic(Duration),
+    /// Changes will be dumped once no change was made for the Duration provided by the developer,
+    /// by a [NoDbFlusher](struct.NoDbFlusher.html) spawned for the DB. Without a flusher, changes are
+    /// dumped when the DB is dropped.
+    Debounced(Duration),
+}
+
+/// An enum that determines how hard NoDb tries to make dumped data survive a crash or a power loss
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Durability {
+    #[default]
+    /// Data is handed over to the operating system, which writes it to the disk at its own pace
+    None,
+    /// The file is synced to the disk before a dump returns
+    SyncFile,
+    /// The file and the directory it is renamed into are synced to the disk before a dump returns
+    SyncAll,
+}
+
+impl Durability {
+    pub(crate) fn sync_file(self, file: &File) -> Result<()> {
+        if self != Durability::None {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn sync_dir<P: AsRef<Path>>(self, path: P) -> Result<()> {
+        if self == Durability::SyncAll {
+            let dir = match path.as_ref().parent() {
+                Some(parent) if parent != Path::new("") => parent,
+                _ => Path::new("."),
+            };
+            // Directories can't be opened as files on every platform.
+            #[cfg(unix)]
+            File::open(dir)?.sync_all()?;
+            #[cfg(not(unix))]
+            let _ = dir;
+        }
+        Ok(())
+    }
+}
+
+/// An enum that determines the policy of keeping rotating backups of the NoDb file next to it
+///
+/// Backups are taken right after a dump succeeds, from the data just written to the file. If a backup
+/// fails while a change is dumped automatically, the change is kept and the backup is taken again on
+/// the next dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackupPolicy {
+    #[default]
+    /// Never back up the file
+    Never,
+    /// The file will be backed up on every dump, keeping the given number of most recent backups.
+    EveryDump(usize),
+    /// The file will be backed up on dumps, no sooner than the Duration provided by the developer
+    /// after the previous backup, keeping the given number of most recent backups.
+    /// Backups are only taken by dumps: a DB that isn't changed, and so isn't dumped, isn't backed up
+    /// either once the Duration has passed.
+    Periodic(Duration, usize),
+}
+
+/// An enum that determines what happens when a NoDb instance holding changes that weren't dumped is
+/// dropped without being [closed](struct.NoDb.html#method.close)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// A warning is printed to the standard error
+    Warn,
+    /// The thread panics, unless it is already panicking
+    Panic,
+    #[default]
+    /// The changes are lost silently
+    Ignore,
+}
+
+/// What a key holds, as named in the errors of the methods that expect it to hold something else.
+#[derive(Debug, Clone, Copy)]
+enum Held {
+    Value,
+    List,
+    Set,
+    Hash,
+    ZSet,
+}
+
+impl Display for Held {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(match self {
+            Held::Value => "a value",
+            Held::List => "a list",
+            Held::Set => "a set",
+            Held::Hash => "a hash",
+            Held::ZSet => "a sorted set",
+        })
+    }
 }
 
 /// A struct that represents a NoDb object.
+///
+/// Changes made through the methods of `NoDb` are all-or-nothing: whenever a method fails, e.g. because
+/// the dump it triggers fails, the values, lists, sets, hashes and sorted sets of the DB are left as they
+/// were before the call.
 pub struct NoDb {
-    pub map: DbMap,
-    pub list_map: DbListMap,
+    pub map: Arc<DbMap>,
+    pub list_map: Arc<DbListMap>,
+    set_map: Arc<DbSetMap>,
+    hash_map: Arc<DbHashMap>,
+    zset_map: Arc<DbZSetMap>,
     ser: Serializer,
     pub path: PathBuf,
     pub policy: DumpPolicy,
+    pub durability: Durability,
+    pub backup_policy: BackupPolicy,
+    pub drop_policy: DropPolicy,
     pub last_dump: Instant,
+    pub last_backup: Instant,
+    last_change: Instant,
+    dirty: bool,
+    dirty_keys: HashSet<String>,
+    wal: Wal,
+    journal: bool,
+    backup: Option<BackupState>,
+    lock: DbLock,
+    pub(crate) flush_signal: Option<Arc<FlushSignal>>,
+    closed: bool,
+    versions: HashMap<String, u64>,
+    version_seq: u64,
+    expires: Arc<DbExpiryMap>,
+    next_expiry: u64,
+    key_index: Option<DbKeyIndex>,
 }
 
 impl NoDb {
     /// Constructs a new `NoDb` instance.
     ///
+    /// The DB is locked for writing, so that no other `NoDb` instance, in this process or another one,
+    /// can open it until this one is dropped. An `anyhow::Error` is returned if the DB is already locked.
+    /// The lock is taken on a `<db>.lock` file created next to the DB file, which is left there once the
+    /// DB is closed: removing it while another instance waits for the lock would let two instances lock
+    /// different files.
+    ///
     /// # Examples
     ///
     /// ```no_run
     /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
     ///
-    /// let mut db = NoDb::new("example.db", DumpPolicy::AutoDump, SerializationMethod::Json);
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
     /// ```
     pub fn new<P: AsRef<Path>>(
         db_path: P,
         policy: DumpPolicy,
         ser_method: SerializationMethod,
-    ) -> Self {
+    ) -> Result<Self> {
         let path = db_path.as_ref().to_path_buf();
 
         if !path.exists() {
-            let parent = path.parent().unwrap();
-            DirBuilder::new().recursive(true).create(parent).unwrap();
+            if let Some(parent) = path.parent() {
+                DirBuilder::new().recursive(true).create(parent)?;
+            }
         }
+        let lock = DbLock::exclusive(&path)?;
 
-        NoDb {
-            map: DbMap::new(),
-            list_map: DbListMap::new(),
+        Ok(NoDb {
+            map: Arc::default(),
+            list_map: Arc::default(),
+            set_map: Arc::default(),
+            hash_map: Arc::default(),
+            zset_map: Arc::default(),
+            expires: Arc::default(),
+            next_expiry: u64::MAX,
             ser: Serializer::from(ser_method),
+            wal: Wal::new(&path),
             path,
             policy,
+            durability: Durability::default(),
+            backup_policy: BackupPolicy::default(),
+            drop_policy: DropPolicy::default(),
             last_dump: Instant::now(),
-        }
+            last_backup: Instant::now(),
+            last_change: Instant::now(),
+            dirty: false,
+            dirty_keys: HashSet::new(),
+            journal: false,
+            backup: None,
+            lock,
+            flush_signal: None,
+            closed: false,
+            versions: HashMap::new(),
+            version_seq: 0,
+            key_index: None,
+        })
     }
 
     /// Loads a `NoDb` instance from a file.
     ///
     /// This method tries to load a DB from a file. Upon success an instance of `Ok(NoDb)` is returned,
     /// otherwise an `anyhow::Error` object is returned.
+    /// Changes recorded in the write-ahead log since the last full dump are replayed on top of the file.
+    ///
+    /// The serialization method recorded in the header of the file takes precedence over `ser_method`,
+    /// which is only used for files written by versions of NoDb that didn't write a header.
+    /// Files written with a newer, incompatible version of the file format are rejected.
+    ///
+    /// The DB is locked for writing as with [new()](#method.new). Use [load_read_only()](#method.load_read_only)
+    /// to share a DB between several readers.
     ///
     /// # Examples
     ///
@@ -94,20 +274,270 @@ impl NoDb {
         policy: DumpPolicy,
         ser_method: SerializationMethod,
     ) -> Result<Self> {
+        let (lock, content) = NoDb::lock_and_read(&db_path)?;
+        let (db, _) = NoDb::from_content(db_path, lock, content, policy, ser_method, false)?;
+        sweep(&db.path)?;
+        Ok(db)
+    }
+
+    /// Loads a `NoDb` instance from a file, recovering it from an interrupted dump if needed.
+    ///
+    /// Dumps write the whole DB to a temporary file next to the file, which is then renamed over it.
+    /// If the file is missing or corrupted, this method looks for the most recent temporary file that
+    /// is complete and intact, and recovers the DB from it. Otherwise this method behaves like
+    /// [load()](#method.load), and the error that prevented the file from being loaded is returned
+    /// if no temporary file could be recovered.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    /// let nodb = NoDb::load_or_recover("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// ```
+    pub fn load_or_recover<P: AsRef<Path>>(
+        db_path: P,
+        policy: DumpPolicy,
+        ser_method: SerializationMethod,
+    ) -> Result<Self> {
+        let lock = DbLock::exclusive(&db_path)?;
+        let loaded = read(&db_path)
+            .map_err(Into::into)
+            .and_then(|content| NoDb::validate(&content, ser_method).map(|_| content));
+        let content = match loaded {
+            Ok(content) => content,
+            Err(err) => {
+                let recovered = temp_files(&db_path)?.into_iter().find_map(|tmp| {
+                    let content = read(&tmp).ok()?;
+                    NoDb::validate(&content, ser_method).ok()?;
+                    Some((tmp, content))
+                });
+                match recovered {
+                    Some((tmp, content)) => {
+                        rename(tmp, &db_path)?;
+                        content
+                    }
+                    None => return Err(err),
+                }
+            }
+        };
+        sweep(&db_path)?;
+        let (db, _) = NoDb::from_content(db_path, lock, content, policy, ser_method, false)?;
+        Ok(db)
+    }
+
+    /// Loads a read-only `NoDb` instance from a file.
+    ///
+    /// This method is similar to [load()](#method.load), except that the DB is only locked for reading:
+    /// any number of read-only instances can share the DB, but none can while another instance has it
+    /// locked for writing, and vice versa. The dump policy of the DB is
+    /// [DumpPolicy::Never](enum.DumpPolicy.html#variant.Never), and dumping it returns an `anyhow::Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, SerializationMethod};
+    /// let nodb = NoDb::load_read_only("example.db", SerializationMethod::Json).unwrap();
+    /// ```
+    pub fn load_read_only<P: AsRef<Path>>(
+        db_path: P,
+        ser_method: SerializationMethod,
+    ) -> Result<Self> {
+        let lock = DbLock::shared(&db_path)?;
+        let content = read(&db_path)?;
+        let (db, _) =
+            NoDb::from_content(db_path, lock, content, DumpPolicy::Never, ser_method, false)?;
+        Ok(db)
+    }
+
+    /// Loads a `NoDb` instance from a damaged file.
+    ///
+    /// Every value, list, set, hash and sorted set of the file is stored along with a checksum, and
+    /// [load()](#method.load) fails as soon as it finds one that is corrupted. This method instead recovers
+    /// every value, list, set, hash and sorted set that is intact, and returns them along with a [SalvageReport](struct.SalvageReport.html)
+    /// of what was lost. The changes recorded in the write-ahead log or the journal are salvaged the same
+    /// way: corrupted records are skipped, and the intact ones are replayed. An `anyhow::Error` is still
+    /// returned if the header of the file is unreadable.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let (nodb, report) =
+    ///     NoDb::load_salvage("example.db", DumpPolicy::Never, SerializationMethod::Json).unwrap();
+    /// if !report.is_clean() {
+    ///     eprintln!("Lost keys: {:?}", report.corrupted_keys);
+    /// }
+    /// ```
+    pub fn load_salvage<P: AsRef<Path>>(
+        db_path: P,
+        policy: DumpPolicy,
+        ser_method: SerializationMethod,
+    ) -> Result<(Self, SalvageReport)> {
+        let (lock, content) = NoDb::lock_and_read(&db_path)?;
+        let (db, report) = NoDb::from_content(db_path, lock, content, policy, ser_method, true)?;
+        sweep(&db.path)?;
+        Ok((db, report))
+    }
+
+    /// Locks the DB for writing and reads the file.
+    ///
+    /// The temporary files left by interrupted dumps are only swept once the file is loaded, so that a
+    /// file that fails to load can still be recovered from them with
+    /// [load_or_recover()](#method.load_or_recover).
+    fn lock_and_read<P: AsRef<Path>>(db_path: P) -> Result<(DbLock, Vec<u8>)> {
+        let lock = DbLock::exclusive(&db_path)?;
         let content = read(&db_path)?;
-        let decrypted_content = B64.decrypt(content)?;
-        let ser = Serializer::from(ser_method);
-        let (map, list_map) = ser.deserialized_db(&decrypted_content)?;
+        Ok((lock, content))
+    }
+
+    /// Checks that the content of a file can be loaded.
+    fn validate(content: &[u8], ser_method: SerializationMethod) -> Result<()> {
+        let payload = format::decode(content, ser_method)?;
+        let ser = Serializer::from(payload.method);
+        read_entries(payload, &ser, false)?;
+        Ok(())
+    }
+
+    fn from_content<P: AsRef<Path>>(
+        db_path: P,
+        lock: DbLock,
+        content: Vec<u8>,
+        policy: DumpPolicy,
+        ser_method: SerializationMethod,
+        salvage: bool,
+    ) -> Result<(Self, SalvageReport)> {
+        let payload = format::decode(&content, ser_method)?;
+        let ser = Serializer::from(payload.method);
+        let (mut data, mut report) = read_entries(payload, &ser, salvage)?;
+        let (wal, corrupted_records) = Wal::load(
+            &db_path,
+            &content,
+            &ser,
+            data.as_mut(),
+            salvage,
+            lock.is_shared(),
+        )?;
+        report.corrupted_records = corrupted_records;
         let path_buf = db_path.as_ref().to_path_buf();
 
-        Ok(NoDb {
-            map,
-            list_map,
+        let db = NoDb {
+            map: Arc::new(data.map),
+            list_map: Arc::new(data.list_map),
+            set_map: Arc::new(data.set_map),
+            hash_map: Arc::new(data.hash_map),
+            zset_map: Arc::new(data.zset_map),
+            next_expiry: next_expiry(&data.expires),
+            expires: Arc::new(data.expires),
             ser,
+            wal,
             path: path_buf,
             policy,
+            durability: Durability::default(),
+            backup_policy: BackupPolicy::default(),
+            drop_policy: DropPolicy::default(),
             last_dump: Instant::now(),
-        })
+            last_backup: Instant::now(),
+            last_change: Instant::now(),
+            dirty: false,
+            dirty_keys: HashSet::new(),
+            journal: false,
+            backup: None,
+            lock,
+            flush_signal: None,
+            closed: false,
+            versions: HashMap::new(),
+            version_seq: 0,
+            key_index: None,
+        };
+        Ok((db, report))
+    }
+
+    /// Opens a `NoDb` instance from a file of unknown serialization method.
+    ///
+    /// This method is similar to [load()](#method.load), except that the serialization method is always
+    /// detected from the header of the file. Files without a header are rejected with an `anyhow::Error`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy};
+    /// let nodb = NoDb::open("example.db", DumpPolicy::Never).unwrap();
+    /// ```
+    pub fn open<P: AsRef<Path>>(db_path: P, policy: DumpPolicy) -> Result<Self> {
+        let (lock, content) = NoDb::lock_and_read(&db_path)?;
+        if !format::has_header(&content) {
+            return Err(anyhow!(
+                "{} has no header, its serialization method can't be detected",
+                db_path.as_ref().display()
+            ));
+        }
+        let (db, _) = NoDb::from_content(
+            db_path,
+            lock,
+            content,
+            policy,
+            SerializationMethod::default(),
+            false,
+        )?;
+        sweep(&db.path)?;
+        Ok(db)
+    }
+
+    /// Enables the crash-recovery journal.
+    ///
+    /// With [DumpPolicy::OnCall](enum.DumpPolicy.html#variant.OnCall),
+    /// [DumpPolicy::Periodic](enum.DumpPolicy.html#variant.Periodic) or
+    /// [DumpPolicy::Debounced](enum.DumpPolicy.html#variant.Debounced), every change that isn't dumped
+    /// right away is appended to the write-ahead log next to the file, so it isn't lost if the process
+    /// crashes before the next dump. The journal is replayed by [load()](#method.load) and truncated
+    /// by [dump()](#method.dump). If the DB was never dumped to the file, it is dumped first.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::load("example.db", DumpPolicy::OnCall, SerializationMethod::Json)
+    ///     .and_then(NoDb::with_journal)
+    ///     .unwrap();
+    /// ```
+    pub fn with_journal(mut self) -> Result<Self> {
+        if !self.wal.has_snapshot() {
+            self.dump()?;
+        }
+        self.journal = true;
+        Ok(self)
+    }
+
+    /// Keeps the keys of the values and lists of the DB in order.
+    ///
+    /// The keys of the values and lists are indexed in a B-tree, so that [iter()](#method.iter) yields the
+    /// values in key order, and [range()](#method.range), [range_rev()](#method.range_rev),
+    /// [scan_prefix()](#method.scan_prefix) and [keys_matching()](#method.keys_matching) find the start of
+    /// a range of keys without reading every key. [get_all()](#method.get_all) returns the keys sorted as
+    /// well. Keeping the index up to date makes setting and removing values and lists a little slower, as
+    /// updating it takes a logarithmic time. Values and lists set or removed directly in
+    /// [map](#structfield.map) or [list_map](#structfield.list_map) are indexed once
+    /// [mark_dirty()](#method.mark_dirty) is called.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json)
+    ///     .unwrap()
+    ///     .with_ordered_keys();
+    /// db.set("b", 2).unwrap();
+    /// db.set("a", 1).unwrap();
+    /// let keys = db.iter().map(|kv| kv.get_key().to_string()).collect::<Vec<_>>();
+    /// assert_eq!(keys, ["a", "b"]);
+    /// ```
+    pub fn with_ordered_keys(mut self) -> Self {
+        self.key_index = Some(DbKeyIndex::new());
+        self.index_keys();
+        self
     }
 
     /// Dump the data to the file.
@@ -117,198 +547,1053 @@ impl NoDb {
     /// is dumped to the file upon every change unless the dump policy is
     /// [DumpPolicy::Never](enum.DumpPolicy.html#variant.Never).
     ///
-    /// This method returns `Ok(())` if dump is successful, Or an `anyhow::Error` otherwise.
+    /// The whole DB is written to the file, and the write-ahead log is discarded.
+    /// The data is synced to the disk according to the [durability](enum.Durability.html) setting,
+    /// and a rotating backup is then taken if the [backup policy](enum.BackupPolicy.html) calls for one.
+    /// The DB is written even if it wasn't changed since it was last dumped; use
+    /// [dump_if_dirty()](#method.dump_if_dirty) to skip unchanged DBs.
+    ///
+    /// This method returns `Ok(())` if dump is successful, Or an `anyhow::Error` otherwise. When only the
+    /// backup fails, the DB was still dumped to the file.
     pub fn dump(&mut self) -> Result<()> {
+        self.check_writable()?;
         if let DumpPolicy::Never = self.policy {
             return Ok(());
         }
-        let data = self.ser.serialize_db(&self.map, &self.list_map)?;
-        let encrypted_data = B64.encrypt(data);
-        let tmp = format!(
-            "{}.tmp.{}",
-            self.path.to_str().unwrap_or("db"),
-            SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .map(|dur| dur.as_secs())
-                .unwrap_or(0)
-        );
-
-        write(&tmp, encrypted_data)?;
-        rename(&tmp, &self.path)?;
-        if let DumpPolicy::Periodic(_) = self.policy {
-            self.last_dump = Instant::now();
-        }
-        Ok(())
-    }
-
-    fn dumpdb(&mut self) -> Result<()> {
-        match self.policy {
-            DumpPolicy::Auto => self.dump(),
-            DumpPolicy::Periodic(dur) => {
-                let now = Instant::now();
-                if now.duration_since(self.last_dump) >= dur {
-                    self.dump()
-                } else {
-                    Ok(())
-                }
-            }
-            _ => Ok(()),
-        }
+        let encoded_data = self.write_snapshot()?;
+        self.backup_if_due(&encoded_data)
     }
 
-    /// Set a key-value pair.
+    /// Dump the data to the file if it was changed since it was last dumped.
     ///
-    /// The key has to be a string but the value can be of any type that is serializable.
-    /// That includes all primitive types, vectors, tuples, enums and every struct that
-    /// has the `#[derive(Serialize, Deserialize)` attribute.
+    /// This method behaves like [dump()](#method.dump), except that nothing is written if the DB is already
+    /// in the file and wasn't changed since, see [is_dirty()](#method.is_dirty). Changes made directly to
+    /// the public maps of the DB are only dumped once [mark_dirty()](#method.mark_dirty) is called.
     ///
-    /// This method returns `Ok(())` if set is successful, Or an `anyhow::Error`
-    /// otherwise. An error is not likely to happen but may occur mostly in cases where this
-    /// action triggers a DB dump (which is decided according to the dump policy).
-    pub fn set<K: AsRef<str>, V: Serialize>(&mut self, key: K, value: V) -> Result<()> {
-        let key = key.as_ref();
-        if self.list_map.contains_key(key) {
-            self.list_map.remove(key);
-        }
-        let data = self.ser.serialize_data(&value)?;
-        let orig_val = self.map.insert(key.to_string(), data);
-        match self.dumpdb() {
-            Ok(_) => Ok(()),
-            Err(err) => {
-                match orig_val {
-                    Some(val) => self.map.insert(String::from(key), val),
-                    None => self.map.remove(key),
-                };
-                Err(err)
-            }
+    /// This method returns `Ok(())` if dump is successful or isn't needed, Or an `anyhow::Error` otherwise.
+    pub fn dump_if_dirty(&mut self) -> Result<()> {
+        if !self.dirty && self.wal.has_snapshot() {
+            return Ok(());
         }
+        self.dump()
     }
 
-    /// Get a value of a key.
+    /// Dump the data to the file and close the DB.
     ///
-    /// The key is always a string but the value can be of any type. It's the developer's
-    /// responsibility to know the value type and give it while calling this method.
-    /// If the key doesn't exist or if the type is wrong, `None` will be returned.
-    /// Otherwise `Some(V)` will be returned.
+    /// Dropping a `NoDb` instance dumps it as well unless the dump policy is
+    /// [DumpPolicy::OnCall](enum.DumpPolicy.html#variant.OnCall), but can't return the errors that
+    /// happen then; they are handled according to the [drop policy](enum.DropPolicy.html) instead.
+    /// This method dumps the DB according to its dump policy if it was changed, like
+    /// [dump_if_dirty()](#method.dump_if_dirty) does, and releases the lock of the file.
     ///
-    /// Since the values are stored in a serialized way the returned object is
-    /// not a reference to the value stored in a DB but actually a new instance
-    /// of it.
-    pub fn get<K: AsRef<str>, V: DeserializeOwned>(&self, key: K) -> Option<V> {
-        let key = key.as_ref();
-        let res = self.map.get(key);
-        if let Some(v) = res {
-            self.ser.deserialize_data(v)
-        } else {
-            None
-        }
+    /// This method returns `Ok(())` if the dump is successful, Or an `anyhow::Error` otherwise. The DB is
+    /// closed either way.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+    /// db.set("key", 100).unwrap();
+    /// db.close().unwrap();
+    /// ```
+    pub fn close(mut self) -> Result<()> {
+        self.closed = true;
+        self.dump_if_dirty()
     }
 
-    /// Check if a key exists.
+    /// Check if the DB was changed since it was last dumped to the file.
     ///
-    /// This method returns `true` if the key exists and `false` otherwise.
-    pub fn exists<K: AsRef<str>>(&self, key: K) -> bool {
-        self.map.contains_key(key.as_ref()) || self.list_map.contains_key(key.as_ref())
+    /// Only changes made through the methods of `NoDb` are tracked. Call [mark_dirty()](#method.mark_dirty)
+    /// after changing [map](#structfield.map) or [list_map](#structfield.list_map) directly, e.g. through
+    /// `Arc::make_mut(&mut db.map)`, as the maps may be shared with [snapshots](#method.snapshot).
+    pub fn is_dirty(&self) -> bool {
+        self.dirty
     }
 
-    /// Get a vector of all the keys in the DB.
-    ///
-    /// The keys returned in the vector are not references to the actual key string
-    /// objects but rather a clone of them.
-    pub fn get_all(&self) -> Vec<String> {
-        [
-            self.map.keys().cloned().collect::<Vec<String>>(),
-            self.list_map.keys().cloned().collect::<Vec<String>>(),
-        ]
-        .concat()
+    /// Get the keys of the values, lists, sets, hashes and sorted sets that were set, changed or removed
+    /// since the DB was last dumped to the file.
+    pub fn dirty_keys(&self) -> Vec<String> {
+        self.dirty_keys.iter().cloned().collect()
     }
 
-    /// Get the total number of keys in the DB.
-    pub fn total_keys(&self) -> usize {
-        self.map.iter().len() + self.list_map.iter().len()
+    /// Mark the DB as changed, so that the next dump writes it to the file.
+    pub fn mark_dirty(&mut self) {
+        self.touch();
+        self.index_keys();
     }
 
-    /// Remove a key-value pair or a list from the DB.
-    ///
-    /// This methods returns `Ok(true)` if the key was found in the DB or `Ok(false)` if it wasn't found.
-    /// It may also return `anyhow::Error` if key was found but removal failed.
-    /// Removal error is not likely to happen but may occur mostly in cases where this action triggers a DB dump
-    /// (which is decided according to the dump policy).
-    pub fn rem<K: AsRef<str>>(&mut self, key: K) -> Result<bool> {
-        let key = key.as_ref();
-        let rm_map = match self.map.remove(key) {
-            None => None,
-            Some(val) => match self.dumpdb() {
-                Ok(_) => Some(val),
-                Err(err) => {
-                    self.map.insert(String::from(key), val);
-                    return Err(err);
-                }
-            },
-        };
-        let rm_list_map = match self.list_map.remove(key) {
-            None => None,
-            Some(val) => match self.dumpdb() {
-                Ok(_) => Some(val),
-                Err(err) => {
-                    self.list_map.insert(String::from(key), val);
-                    return Err(err);
-                }
-            },
-        };
-        Ok(rm_map.is_some() || rm_list_map.is_some())
+    /// Marks the DB as changed by one of its methods, which keep the index of the keys up to date.
+    fn touch(&mut self) {
+        self.dirty = true;
+        self.last_change = Instant::now();
     }
 
-    /// Create a new list.
-    ///
-    /// This method just creates a new list, it doesn't add any elements to it.
-    /// If another list or value is already set under this key, they will be overridden,
-    /// meaning the new list will override the old list or value.
-    ///
-    /// Upon success, the method returns an object of type
-    /// [NoDbExt](struct.NoDbExt.html) that enables to add
-    /// items to the newly created list. Alternatively you can use [list_add()](#method.list_add)
-    /// or [list_extend()](#method.list_extend) to add items to the list.
-    pub fn list_create<N: AsRef<str>>(&mut self, name: N) -> Result<NoDbExt> {
-        let new_list = Vec::new();
-        let name = name.as_ref();
-        if self.map.contains_key(name) {
-            self.map.remove(name);
+    /// Rebuilds the index of the keys of the values and lists, if the DB keeps one.
+    fn index_keys(&mut self) {
+        if let Some(index) = &mut self.key_index {
+            *index = self
+                .map
+                .keys()
+                .chain(self.list_map.keys())
+                .cloned()
+                .collect();
         }
-        self.list_map.insert(String::from(name), new_list);
-        self.dumpdb()?;
-        Ok(NoDbExt {
-            db: self,
-            list_name: name.to_string(),
-        })
     }
 
-    /// Check if a list exists.
-    ///
-    /// This method returns `true` if the list name exists and `false` otherwise.
-    /// The difference between this method and [exists()](#method.exists) is that this methods checks only
-    /// for lists with that name (key) and [exists()](#method.exists) checks for both values and lists.
-    pub fn list_exists<N: AsRef<str>>(&self, name: N) -> bool {
-        self.list_map.contains_key(name.as_ref())
+    /// Returns how long until the DB is due to be dumped by a flusher, or `None` if it isn't.
+    pub(crate) fn flush_due_in(&self) -> Option<Duration> {
+        if !self.dirty {
+            return None;
+        }
+        match self.policy {
+            DumpPolicy::Periodic(dur) => Some(dur.saturating_sub(self.last_dump.elapsed())),
+            DumpPolicy::Debounced(dur) => Some(dur.saturating_sub(self.last_change.elapsed())),
+            _ => None,
+        }
     }
 
-    /// Add a single item to an existing list.
-    ///
-    /// As mentioned before, the lists are heterogeneous, meaning a single list can contain
-    /// items of different types. That means that the item can be of any type that is serializable.
-    /// That includes all primitive types, vectors, tuples and every struct that has the
-    /// `#[derive(Serialize, Deserialize)` attribute.
+    fn check_writable(&self) -> Result<()> {
+        if self.lock.is_shared() {
+            return Err(anyhow!("{} is opened read-only", self.path.display()));
+        }
+        Ok(())
+    }
+
+    /// Writes data to a temporary file that is then renamed to `path`, so that readers of `path`
+    /// never see a partially written file.
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let tmp = temp_path(path);
+        if let Err(err) = self.write_and_rename(&tmp, path, data) {
+            let _ = remove_file(&tmp);
+            return Err(err);
+        }
+        self.durability.sync_dir(path)
+    }
+
+    fn write_and_rename(&self, tmp: &Path, path: &Path, data: &[u8]) -> Result<()> {
+        let mut file = File::create(tmp)?;
+        file.write_all(data)?;
+        self.durability.sync_file(&file)?;
+        drop(file);
+        rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Writes the whole DB to the file and discards the write-ahead log.
     ///
-    /// If the item was added successfully the method returns
-    /// `Some(`[NoDbExt](struct.NoDbExt.html)`)` which enables to add more
-    /// items to the list. Alternatively the method returns `None` if the list isn't found in the DB
-    /// or if a failure happened while extending the list. Failures are not likely to happen but may
-    /// occur mostly in cases where this action triggers a DB dump (which is decided according to the dump policy).
-    pub fn list_add<K: AsRef<str>, V: Serialize>(&mut self, name: K, value: &V) -> Option<NoDbExt> {
-        self.list_extend(name, &[value])
+    /// Returns the content written to the file.
+    fn write_snapshot(&mut self) -> Result<Vec<u8>> {
+        self.check_writable()?;
+        let encoded_data = self.encode()?;
+        self.write_file(&self.path, &encoded_data)?;
+        self.wal.reset(&encoded_data);
+        self.last_dump = Instant::now();
+        self.dirty = false;
+        self.dirty_keys.clear();
+        Ok(encoded_data)
     }
 
-    /// Add multiple items to an existing list.
+    /// Dumps the DB after a change. Once the change is in the file, a failed rotating backup doesn't
+    /// fail it: the backup is still due, and is taken again on the next dump.
+    fn dump_change(&mut self) -> Result<()> {
+        let encoded_data = self.write_snapshot()?;
+        let _ = self.backup_if_due(&encoded_data);
+        Ok(())
+    }
+
+    /// Takes a rotating backup of a snapshot written to the file, if the backup policy calls for one.
+    fn backup_if_due(&mut self, snapshot: &[u8]) -> Result<()> {
+        let keep = match self.backup_policy {
+            BackupPolicy::EveryDump(keep) => Some(keep),
+            BackupPolicy::Periodic(dur, keep) if self.last_backup.elapsed() >= dur => Some(keep),
+            _ => None,
+        };
+        if let Some(keep) = keep {
+            self.write_rotated_backup(snapshot, keep)?;
+        }
+        Ok(())
+    }
+
+    fn write_rotated_backup(&mut self, snapshot: &[u8], keep: usize) -> Result<PathBuf> {
+        let backup_path = rotated_path(&self.path)?;
+        self.write_file(&backup_path, snapshot)?;
+        prune_backups(&self.path, keep)?;
+        self.last_backup = Instant::now();
+        Ok(backup_path)
+    }
+
+    /// Encodes the whole DB in the format of the DB file.
+    ///
+    /// Keys that are already expired are left out.
+    fn encode(&self) -> Result<Vec<u8>> {
+        let now = ttl::now();
+        let mut entries = Vec::with_capacity(self.keys().count());
+        for (key, value) in self.map.iter() {
+            if is_expired(&self.expires, key, now) {
+                continue;
+            }
+            entries.push((
+                EntryKind::Value,
+                self.ser.serialize_value(key, value)?,
+                self.expires.get(key).copied(),
+            ));
+        }
+        for (name, list) in self.list_map.iter() {
+            if is_expired(&self.expires, name, now) {
+                continue;
+            }
+            entries.push((
+                EntryKind::List,
+                self.ser.serialize_list(name, list)?,
+                self.expires.get(name).copied(),
+            ));
+        }
+        for (name, set) in self.set_map.iter() {
+            if is_expired(&self.expires, name, now) {
+                continue;
+            }
+            entries.push((
+                EntryKind::Set,
+                self.ser.serialize_set(name, set)?,
+                self.expires.get(name).copied(),
+            ));
+        }
+        for (name, hash) in self.hash_map.iter() {
+            if is_expired(&self.expires, name, now) {
+                continue;
+            }
+            entries.push((
+                EntryKind::Hash,
+                self.ser.serialize_hash(name, hash)?,
+                self.expires.get(name).copied(),
+            ));
+        }
+        for (name, zset) in self.zset_map.iter() {
+            if is_expired(&self.expires, name, now) {
+                continue;
+            }
+            entries.push((
+                EntryKind::ZSet,
+                self.ser.serialize_zset(name, zset)?,
+                self.expires.get(name).copied(),
+            ));
+        }
+        Ok(format::encode(self.ser.method(), &entries))
+    }
+
+    fn dumpdb(&mut self, op: LogOp) -> Result<()> {
+        if self.policy != DumpPolicy::Never {
+            self.check_writable()?;
+        }
+        if let Some(backup) = &mut self.backup {
+            backup.track(&op);
+        }
+        let was_dirty = self.dirty;
+        self.touch();
+        let keys = op.keys().into_iter().map(String::from).collect::<Vec<_>>();
+        let mut new_dirty_keys = Vec::new();
+        for key in keys.iter() {
+            if !self.dirty_keys.contains(key) {
+                self.dirty_keys.insert(key.to_string());
+                new_dirty_keys.push(key);
+            }
+        }
+        if let Some(signal) = &self.flush_signal {
+            signal.notify();
+        }
+        let res = match self.policy {
+            DumpPolicy::Auto => {
+                if self.wal.is_full() {
+                    self.dump_change()
+                } else {
+                    self.wal.append(&self.ser, op, self.durability)
+                }
+            }
+            DumpPolicy::Periodic(dur) => {
+                let now = Instant::now();
+                if now.duration_since(self.last_dump) >= dur {
+                    self.dump_change()
+                } else if self.journal {
+                    self.wal.append(&self.ser, op, self.durability)
+                } else {
+                    Ok(())
+                }
+            }
+            DumpPolicy::OnCall | DumpPolicy::Debounced(_) if self.journal => {
+                self.wal.append(&self.ser, op, self.durability)
+            }
+            _ => Ok(()),
+        };
+        match res {
+            Ok(_) => self.bump_versions(keys),
+            Err(_) => {
+                self.dirty = was_dirty;
+                for key in new_dirty_keys {
+                    self.dirty_keys.remove(key);
+                }
+            }
+        }
+        res
+    }
+
+    /// Gives the changed keys a new version.
+    fn bump_versions<I: IntoIterator<Item = String>>(&mut self, keys: I) {
+        self.version_seq += 1;
+        for key in keys {
+            self.versions.insert(key, self.version_seq);
+        }
+    }
+
+    /// Applies several changes to the DB with a single dump, along with the removal of the keys
+    /// that expired, if any.
+    fn commit(&mut self, ops: Vec<LogOp>) -> Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let now = ttl::now();
+        if self.next_expiry <= now {
+            let mut expired = self.expired_ops(now);
+            expired.extend(ops);
+            self.apply_ops(expired, true)
+        } else {
+            self.apply_ops(ops, false)
+        }
+    }
+
+    /// Applies several changes to the DB with a single dump, restoring the keys they change if the
+    /// dump fails.
+    fn apply_ops(&mut self, mut ops: Vec<LogOp>, purge: bool) -> Result<()> {
+        let expiring = ops
+            .iter()
+            .any(|op| matches!(op, LogOp::Expire { at: Some(_), .. }));
+        let op = match ops.len() {
+            0 => {
+                self.next_expiry = next_expiry(&self.expires);
+                return Ok(());
+            }
+            1 => ops.remove(0),
+            _ => LogOp::Batch { ops },
+        };
+
+        let mut data = self.data_mut();
+        let mut orig = Vec::new();
+        for key in op.keys() {
+            if !orig.iter().any(|(orig_key, _)| orig_key == key) {
+                orig.push((key.to_string(), data.get(key)));
+            }
+        }
+        op.clone().apply(&mut data);
+
+        if let Err(err) = self.dumpdb(op) {
+            let mut data = self.data_mut();
+            for (key, key_data) in orig {
+                data.put(&key, key_data);
+            }
+            return Err(err);
+        }
+        if purge || expiring {
+            self.next_expiry = next_expiry(&self.expires);
+        }
+        Ok(())
+    }
+
+    /// Returns the changes removing the keys expired at the time `now`.
+    fn expired_ops(&self, now: u64) -> Vec<LogOp> {
+        self.expires
+            .iter()
+            .filter(|(_, at)| **at <= now)
+            .map(|(key, _)| LogOp::Rem {
+                key: key.to_string(),
+            })
+            .collect()
+    }
+
+    /// Returns how long until the next key expires, or `None` if no key expires.
+    pub(crate) fn purge_due_in(&self) -> Option<Duration> {
+        if self.next_expiry == u64::MAX {
+            return None;
+        }
+        Some(Duration::from_millis(
+            self.next_expiry.saturating_sub(ttl::now()),
+        ))
+    }
+
+    /// Write a full backup of the DB to a file.
+    ///
+    /// The backup has the same format as the DB file, so it can be opened with [load()](#method.load).
+    /// From then on the DB keeps track of the keys that change, so that following backups can be
+    /// written with [backup_incremental()](#method.backup_incremental).
+    ///
+    /// The backup is a consistent copy of the DB at the time of the call, written to a temporary
+    /// file first so that it can be taken while the DB file is being dumped to, and synced to the disk
+    /// according to the [durability](enum.Durability.html) setting.
+    ///
+    /// This method returns `Ok(())` if the backup is successful, Or an `anyhow::Error` otherwise.
+    pub fn backup_to<P: AsRef<Path>>(&mut self, backup_path: P) -> Result<()> {
+        let snapshot = self.encode()?;
+        self.write_file(backup_path.as_ref(), &snapshot)?;
+        self.backup = Some(BackupState::new(&snapshot));
+        Ok(())
+    }
+
+    /// Take a rotating backup of the DB.
+    ///
+    /// The backup is written next to the file, named after it with the time it was taken
+    /// (`<db>.<millis>.bak`), and only the `keep` most recent rotating backups are kept.
+    /// Rotating backups are also taken on dumps according to the [backup policy](enum.BackupPolicy.html).
+    ///
+    /// This method returns the path of the backup if it is successful, Or an `anyhow::Error` otherwise.
+    pub fn rotate_backup(&mut self, keep: usize) -> Result<PathBuf> {
+        let snapshot = self.encode()?;
+        self.write_rotated_backup(&snapshot, keep)
+    }
+
+    /// Get the paths of the rotating backups of the DB, oldest first.
+    pub fn backups(&self) -> Result<Vec<PathBuf>> {
+        rotated_backups(&self.path)
+    }
+
+    /// Write an incremental backup of the DB to a file.
+    ///
+    /// The backup only holds the values, lists, sets, hashes and sorted sets that were set, changed or removed since the previous
+    /// backup, full or incremental, and can only be restored on top of it with
+    /// [restore_backup()](#method.restore_backup). Like a full backup, it is written to a temporary file
+    /// first and synced to the disk according to the [durability](enum.Durability.html) setting.
+    ///
+    /// This method returns an `anyhow::Error` if no full backup was written with
+    /// [backup_to()](#method.backup_to) beforehand, or if writing the backup fails.
+    pub fn backup_incremental<P: AsRef<Path>>(&mut self, backup_path: P) -> Result<()> {
+        let backup = self
+            .backup
+            .as_ref()
+            .ok_or_else(|| anyhow!("No full backup to write an incremental backup on top of"))?;
+        let content = backup.incremental(&self.ser, |key| self.key_ops(key))?;
+        self.write_file(backup_path.as_ref(), content.as_bytes())?;
+        self.backup = Some(BackupState::new(content.as_bytes()));
+        Ok(())
+    }
+
+    /// Restore the DB from a backup, replacing all of its data.
+    ///
+    /// The backup, such as one written with [backup_to()](#method.backup_to) or a rotating backup,
+    /// is fully validated first: it must have been written with the same serialization method as
+    /// the DB, and every value, list, set, hash and sorted set in it must be intact. Only then are the data in memory and
+    /// the file replaced, unless the dump policy is [DumpPolicy::Never](enum.DumpPolicy.html#variant.Never),
+    /// in which case only the data in memory is replaced.
+    ///
+    /// This method returns `Ok(())` if the restore is successful, Or an `anyhow::Error` otherwise,
+    /// in which case neither the data in memory nor the file are changed.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::load("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// if let Some(backup) = db.backups().unwrap().last() {
+    ///     db.restore_from(backup).unwrap();
+    /// }
+    /// ```
+    pub fn restore_from<P: AsRef<Path>>(&mut self, backup_path: P) -> Result<()> {
+        let content = read(&backup_path)?;
+        let payload = format::decode(&content, self.ser.method())?;
+        if payload.method != self.ser.method() {
+            return Err(anyhow!(
+                "{} was written with {}, the DB uses {}",
+                backup_path.as_ref().display(),
+                payload.method,
+                self.ser.method()
+            ));
+        }
+        let (data, report) = read_entries(payload, &self.ser, false)?;
+        let key_count = data.keys().count();
+        if report.recovered != key_count {
+            return Err(anyhow!(
+                "{} holds {} entries for {} keys",
+                backup_path.as_ref().display(),
+                report.recovered,
+                key_count
+            ));
+        }
+
+        let keys = self.keys().chain(data.keys()).cloned().collect::<Vec<_>>();
+        if self.policy != DumpPolicy::Never {
+            self.check_writable()?;
+            self.write_file(&self.path, &content)?;
+            self.wal.reset(&content);
+            self.dirty = false;
+            self.dirty_keys.clear();
+        } else {
+            self.touch();
+            self.dirty_keys.extend(keys.iter().cloned());
+        }
+        if let Some(backup) = &mut self.backup {
+            for key in keys.iter() {
+                backup.track_key(key);
+            }
+        }
+        self.bump_versions(keys);
+        self.map = Arc::new(data.map);
+        self.list_map = Arc::new(data.list_map);
+        self.set_map = Arc::new(data.set_map);
+        self.hash_map = Arc::new(data.hash_map);
+        self.zset_map = Arc::new(data.zset_map);
+        self.next_expiry = next_expiry(&data.expires);
+        self.expires = Arc::new(data.expires);
+        self.index_keys();
+        Ok(())
+    }
+
+    /// Restore a DB from a chain of backups.
+    ///
+    /// This method loads the full backup written with [backup_to()](#method.backup_to), applies the
+    /// incremental backups written with [backup_incremental()](#method.backup_incremental) in order,
+    /// and dumps the result to `db_path` according to the dump policy. Incremental backups that don't
+    /// follow each other in the chain are rejected with an `anyhow::Error`. As with [load()](#method.load),
+    /// the serialization method recorded in the header of the full backup takes precedence over `ser_method`.
+    ///
+    /// Incremental backups of the restored DB can be written on top of the last backup of the chain.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let db = NoDb::restore_backup(
+    ///     "example.db",
+    ///     "backup/full.db",
+    ///     ["backup/inc-1.db", "backup/inc-2.db"],
+    ///     DumpPolicy::Auto,
+    ///     SerializationMethod::Json,
+    /// )
+    /// .unwrap();
+    /// ```
+    pub fn restore_backup<P, B, I>(
+        db_path: P,
+        full_backup: B,
+        incremental_backups: I,
+        policy: DumpPolicy,
+        ser_method: SerializationMethod,
+    ) -> Result<Self>
+    where
+        P: AsRef<Path>,
+        B: AsRef<Path>,
+        I: IntoIterator,
+        I::Item: AsRef<Path>,
+    {
+        let content = read(full_backup)?;
+        let payload = format::decode(&content, ser_method)?;
+        let mut db = NoDb::new(db_path, policy, payload.method)?;
+        let (mut data, _) = read_entries(payload, &db.ser, false)?;
+        let mut base = checksum(&content);
+        for backup in incremental_backups {
+            base = apply_incremental(backup, base, &db.ser, data.as_mut())?;
+        }
+
+        db.map = Arc::new(data.map);
+        db.list_map = Arc::new(data.list_map);
+        db.set_map = Arc::new(data.set_map);
+        db.hash_map = Arc::new(data.hash_map);
+        db.zset_map = Arc::new(data.zset_map);
+        db.next_expiry = next_expiry(&data.expires);
+        db.expires = Arc::new(data.expires);
+        db.dump()?;
+        db.backup = Some(BackupState::from_checksum(base));
+        Ok(db)
+    }
+
+    /// Set a key-value pair.
+    ///
+    /// The key has to be a string but the value can be of any type that is serializable.
+    /// That includes all primitive types, vectors, tuples, enums and every struct that
+    /// has the `#[derive(Serialize, Deserialize)` attribute.
+    ///
+    /// This method returns `Ok(())` if set is successful, Or an `anyhow::Error`
+    /// otherwise. An error is not likely to happen but may occur mostly in cases where this
+    /// action triggers a DB dump (which is decided according to the dump policy).
+    pub fn set<K: AsRef<str>, V: Serialize>(&mut self, key: K, value: V) -> Result<()> {
+        let value = self.ser.serialize_data(&value)?;
+        self.commit(vec![LogOp::Set {
+            key: key.as_ref().to_string(),
+            value,
+        }])
+    }
+
+    /// Get a value of a key.
+    ///
+    /// The key is always a string but the value can be of any type. It's the developer's
+    /// responsibility to know the value type and give it while calling this method.
+    /// If the key doesn't exist or if the type is wrong, `None` will be returned.
+    /// Otherwise `Some(V)` will be returned.
+    ///
+    /// Since the values are stored in a serialized way the returned object is
+    /// not a reference to the value stored in a DB but actually a new instance
+    /// of it.
+    pub fn get<K: AsRef<str>, V: DeserializeOwned>(&self, key: K) -> Option<V> {
+        let key = key.as_ref();
+        let res = self.value(key);
+        if let Some(v) = res {
+            self.ser.deserialize_data(v)
+        } else {
+            None
+        }
+    }
+
+    /// Check if a key exists.
+    ///
+    /// This method returns `true` if the key exists and `false` otherwise.
+    pub fn exists<K: AsRef<str>>(&self, key: K) -> bool {
+        self.held(key.as_ref()).is_some()
+    }
+
+    /// Get a vector of all the keys in the DB.
+    ///
+    /// The keys returned in the vector are not references to the actual key string
+    /// objects but rather a clone of them.
+    pub fn get_all(&self) -> Vec<String> {
+        let now = ttl::now();
+        let mut keys = self
+            .keys()
+            .filter(|key| !is_expired(&self.expires, key, now))
+            .cloned()
+            .collect::<Vec<_>>();
+        if self.key_index.is_some() {
+            keys.sort_unstable();
+        }
+        keys
+    }
+
+    /// Get the total number of keys in the DB.
+    pub fn total_keys(&self) -> usize {
+        if self.expires.is_empty() {
+            return self.map.len()
+                + self.list_map.len()
+                + self.set_map.len()
+                + self.hash_map.len()
+                + self.zset_map.len();
+        }
+        let now = ttl::now();
+        self.keys()
+            .filter(|key| !is_expired(&self.expires, key, now))
+            .count()
+    }
+
+    /// Set a key-value pair, unless the key already exists.
+    ///
+    /// This method returns `Ok(true)` if the value was set and `Ok(false)` if a value, a list, a set, a
+    /// hash or a sorted set is already set under this key. Otherwise it behaves like [set()](#method.set).
+    pub fn set_if_absent<K: AsRef<str>, V: Serialize>(&mut self, key: K, value: V) -> Result<bool> {
+        if self.exists(&key) {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    /// Set a key-value pair, only if the current value of the key is equal to an expected value.
+    ///
+    /// The current value is deserialized to the type of the expected value before they are compared, so
+    /// the write doesn't happen if the key doesn't exist or if its value is of another type.
+    ///
+    /// This method returns `Ok(true)` if the value was set and `Ok(false)` otherwise. An `anyhow::Error`
+    /// is returned if setting the value fails, as with [set()](#method.set).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.set("counter", 1).unwrap();
+    ///
+    /// assert!(db.set_if_eq("counter", &1, &2).unwrap());
+    /// // the value was changed in between
+    /// assert!(!db.set_if_eq("counter", &1, &2).unwrap());
+    /// ```
+    pub fn set_if_eq<K, V>(&mut self, key: K, expected: &V, value: &V) -> Result<bool>
+    where
+        K: AsRef<str>,
+        V: Serialize + DeserializeOwned + PartialEq,
+    {
+        if self.get::<_, V>(&key).as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    /// Remove a key-value pair, only if its value is equal to an expected value.
+    ///
+    /// The value is compared as with [set_if_eq()](#method.set_if_eq). This method returns `Ok(true)` if
+    /// the key was removed and `Ok(false)` otherwise. An `anyhow::Error` is returned if the removal fails,
+    /// as with [rem()](#method.rem).
+    pub fn rem_if_eq<K, V>(&mut self, key: K, expected: &V) -> Result<bool>
+    where
+        K: AsRef<str>,
+        V: DeserializeOwned + PartialEq,
+    {
+        if self.get::<_, V>(&key).as_ref() != Some(expected) {
+            return Ok(false);
+        }
+        self.rem(key)
+    }
+
+    /// Get the version of a key.
+    ///
+    /// Every change made to a value, a list, a set, a hash or a sorted set through the methods of `NoDb` gives its key a new version,
+    /// greater than every version given before, including when the key is removed. Keys that weren't
+    /// changed since the DB was created or loaded have the version 0, as versions aren't dumped to the file.
+    ///
+    /// Versions can be passed to [set_if_version()](#method.set_if_version) and
+    /// [rem_if_version()](#method.rem_if_version) to only write a key if it wasn't changed in between.
+    pub fn version<K: AsRef<str>>(&self, key: K) -> u64 {
+        self.versions.get(key.as_ref()).copied().unwrap_or(0)
+    }
+
+    /// Set a key-value pair, only if the version of the key is equal to an expected version.
+    ///
+    /// This method returns `Ok(true)` if the value was set and `Ok(false)` if the key was changed
+    /// since `version` was read with [version()](#method.version). An `anyhow::Error` is returned if
+    /// setting the value fails, as with [set()](#method.set).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.set("counter", 1).unwrap();
+    ///
+    /// let version = db.version("counter");
+    /// let counter = db.get::<_, i32>("counter").unwrap();
+    /// assert!(db.set_if_version("counter", version, counter + 1).unwrap());
+    /// ```
+    pub fn set_if_version<K: AsRef<str>, V: Serialize>(
+        &mut self,
+        key: K,
+        version: u64,
+        value: V,
+    ) -> Result<bool> {
+        if self.version(&key) != version {
+            return Ok(false);
+        }
+        self.set(key, value)?;
+        Ok(true)
+    }
+
+    /// Remove a key-value pair, a list, a set, a hash or a sorted set, only if the version of the key is equal to an expected version.
+    ///
+    /// This method returns `Ok(true)` if the key was removed and `Ok(false)` if it doesn't exist or if it
+    /// was changed since `version` was read with [version()](#method.version). An `anyhow::Error` is
+    /// returned if the removal fails, as with [rem()](#method.rem).
+    pub fn rem_if_version<K: AsRef<str>>(&mut self, key: K, version: u64) -> Result<bool> {
+        if self.version(&key) != version {
+            return Ok(false);
+        }
+        self.rem(key)
+    }
+
+    /// Increment the integer value of a key by one.
+    ///
+    /// This method is a shortcut for [incr_by(key, 1)](#method.incr_by).
+    pub fn incr<K: AsRef<str>>(&mut self, key: K) -> Result<i64> {
+        self.incr_by(key, 1)
+    }
+
+    /// Increment the integer value of a key.
+    ///
+    /// The value is read as an `i64`, incremented and set back with a single dump. If the key doesn't
+    /// exist, it is set to `by` as if its value was 0. A time to live set on the key is kept.
+    ///
+    /// This method returns `Ok(value)` with the new value of the key. An `anyhow::Error` is returned and
+    /// the value is left untouched if it isn't an integer, if the key holds a collection, if the result
+    /// overflows an `i64`, or if the dump fails. Note that with serialization methods that don't describe
+    /// the type of the data (e.g. [SerializationMethod::Bin](enum.SerializationMethod.html#variant.Bin)),
+    /// a value is read as an integer if it has the exact same encoding as one, e.g. a `u64` or an `f64`.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// assert_eq!(db.incr_by("visits", 10).unwrap(), 10);
+    /// assert_eq!(db.incr("visits").unwrap(), 11);
+    /// assert_eq!(db.decr_by("visits", 5).unwrap(), 6);
+    /// ```
+    pub fn incr_by<K: AsRef<str>>(&mut self, key: K, by: i64) -> Result<i64> {
+        let key = key.as_ref();
+        let value = match self.counter::<i64>(key)? {
+            Some(value) => value
+                .checked_add(by)
+                .ok_or_else(|| anyhow!("Incrementing {} overflows", key))?,
+            None => by,
+        };
+        self.set_counter(key, value)?;
+        Ok(value)
+    }
+
+    /// Decrement the integer value of a key.
+    ///
+    /// This method behaves like [incr_by()](#method.incr_by), subtracting `by` from the value instead.
+    pub fn decr_by<K: AsRef<str>>(&mut self, key: K, by: i64) -> Result<i64> {
+        let key = key.as_ref();
+        let value = match self.counter::<i64>(key)? {
+            Some(value) => value
+                .checked_sub(by)
+                .ok_or_else(|| anyhow!("Decrementing {} overflows", key))?,
+            None => 0i64
+                .checked_sub(by)
+                .ok_or_else(|| anyhow!("Decrementing {} overflows", key))?,
+        };
+        self.set_counter(key, value)?;
+        Ok(value)
+    }
+
+    /// Increment the floating point value of a key.
+    ///
+    /// The value is read as an `f64`, incremented and set back with a single dump. If the key doesn't
+    /// exist, it is set to `by` as if its value was 0. Integer values are read as floating point values.
+    ///
+    /// Floating point counters are only supported by serialization methods that describe the type of the
+    /// data, e.g. [SerializationMethod::Json](enum.SerializationMethod.html#variant.Json). The others
+    /// (e.g. [SerializationMethod::Bin](enum.SerializationMethod.html#variant.Bin)) encode an `f64` and an
+    /// `i64` the same way, so the value of a key couldn't be told apart from the value of an integer
+    /// counter.
+    ///
+    /// This method returns `Ok(value)` with the new value of the key. An `anyhow::Error` is returned and
+    /// the value is left untouched if the serialization method doesn't support floating point counters,
+    /// if the value isn't a number, if the key holds a collection, if the result isn't finite, or if the
+    /// dump fails.
+    pub fn incr_by_float<K: AsRef<str>>(&mut self, key: K, by: f64) -> Result<f64> {
+        let key = key.as_ref();
+        if !self.ser.is_self_describing() {
+            return Err(anyhow!(
+                "Floating point counters aren't supported by {}",
+                self.ser.method()
+            ));
+        }
+        let value = self.counter::<f64>(key)?.unwrap_or(0.0) + by;
+        if !value.is_finite() {
+            return Err(anyhow!("Incrementing {} results in {}", key, value));
+        }
+        self.set_counter(key, value)?;
+        Ok(value)
+    }
+
+    /// Reads the numeric value of a key, or `None` if the key doesn't exist.
+    fn counter<V: Serialize + DeserializeOwned>(&self, key: &str) -> Result<Option<V>> {
+        match self.held(key) {
+            None | Some(Held::Value) => {}
+            Some(held) => return Err(anyhow!("{} holds {}, not a number", key, held)),
+        }
+        match self.value(key) {
+            Some(value) => match self.number(value) {
+                Some(value) => Ok(Some(value)),
+                None => Err(anyhow!("{} doesn't hold a number", key)),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Reads serialized data as a number.
+    ///
+    /// With serialization methods that don't describe the type of the data, the prefix of other data can
+    /// be read as a number too, so the number is only accepted if it serializes back to the same data.
+    fn number<V: Serialize + DeserializeOwned>(&self, data: &[u8]) -> Option<V> {
+        let value = self.ser.deserialize_data(data)?;
+        if self.ser.is_self_describing() || self.ser.serialize_data(&value).ok()? == data {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Sets the numeric value of a key, keeping its time to live.
+    fn set_counter<V: Serialize>(&mut self, key: &str, value: V) -> Result<()> {
+        let at = self.value(key).and(self.expires.get(key).copied());
+        let mut ops = vec![LogOp::Set {
+            key: key.to_string(),
+            value: self.ser.serialize_data(&value)?,
+        }];
+        if at.is_some() {
+            ops.push(LogOp::Expire {
+                key: key.to_string(),
+                at,
+            });
+        }
+        self.commit(ops)
+    }
+
+    /// Remove a key-value pair, a list, a set, a hash or a sorted set from the DB.
+    ///
+    /// This methods returns `Ok(true)` if the key was found in the DB or `Ok(false)` if it wasn't found.
+    /// It may also return `anyhow::Error` if key was found but removal failed.
+    /// Removal error is not likely to happen but may occur mostly in cases where this action triggers a DB dump
+    /// (which is decided according to the dump policy).
+    pub fn rem<K: AsRef<str>>(&mut self, key: K) -> Result<bool> {
+        if !self.exists(&key) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::Rem {
+            key: key.as_ref().to_string(),
+        }])?;
+        Ok(true)
+    }
+
+    /// Set a key-value pair that expires after a time to live.
+    ///
+    /// Once the time to live has passed, the key is hidden from every read as if it was removed, and is
+    /// removed from the DB along with the next change made to it. The time the key expires is dumped to
+    /// the file along with its value, so it survives loading the DB again. Setting the key again with
+    /// [set()](#method.set) makes it persistent.
+    ///
+    /// This method returns `Ok(())` if set is successful, Or an `anyhow::Error` otherwise, as with
+    /// [set()](#method.set).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::time::Duration;
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.set_with_ttl("session", "token", Duration::from_secs(60)).unwrap();
+    /// assert!(db.ttl("session").unwrap() <= Duration::from_secs(60));
+    /// ```
+    pub fn set_with_ttl<K: AsRef<str>, V: Serialize>(
+        &mut self,
+        key: K,
+        value: V,
+        ttl: Duration,
+    ) -> Result<()> {
+        let key = key.as_ref();
+        let value = self.ser.serialize_data(&value)?;
+        self.commit(vec![
+            LogOp::Set {
+                key: key.to_string(),
+                value,
+            },
+            LogOp::Expire {
+                key: key.to_string(),
+                at: Some(expires_at(ttl)),
+            },
+        ])
+    }
+
+    /// Set the time to live of a value, a list, a set, a hash or a sorted set.
+    ///
+    /// The key expires once the time to live has passed, as with [set_with_ttl()](#method.set_with_ttl),
+    /// replacing any time to live it had before.
+    ///
+    /// This method returns `Ok(true)` if the key was found in the DB or `Ok(false)` if it wasn't found.
+    /// An `anyhow::Error` is returned if the dump triggered by this change fails.
+    pub fn expire<K: AsRef<str>>(&mut self, key: K, ttl: Duration) -> Result<bool> {
+        if !self.exists(&key) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::Expire {
+            key: key.as_ref().to_string(),
+            at: Some(expires_at(ttl)),
+        }])?;
+        Ok(true)
+    }
+
+    /// Get the remaining time to live of a value, a list, a set, a hash or a sorted set.
+    ///
+    /// This method returns `None` if the key doesn't exist or doesn't expire.
+    pub fn ttl<K: AsRef<str>>(&self, key: K) -> Option<Duration> {
+        let key = key.as_ref();
+        if !self.exists(key) {
+            return None;
+        }
+        let at = self.expires.get(key)?;
+        Some(Duration::from_millis(at.saturating_sub(ttl::now())))
+    }
+
+    /// Remove the time to live of a value, a list, a set, a hash or a sorted set, so that it doesn't expire anymore.
+    ///
+    /// This method returns `Ok(true)` if the key had a time to live or `Ok(false)` otherwise.
+    /// An `anyhow::Error` is returned if the dump triggered by this change fails.
+    pub fn persist<K: AsRef<str>>(&mut self, key: K) -> Result<bool> {
+        if self.ttl(&key).is_none() {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::Expire {
+            key: key.as_ref().to_string(),
+            at: None,
+        }])?;
+        Ok(true)
+    }
+
+    /// Remove the keys that expired from the DB.
+    ///
+    /// Expired keys are already hidden from reads, and removed along with every change made to the DB,
+    /// so calling this method is only needed to free their memory sooner. A
+    /// [NoDbFlusher](struct.NoDbFlusher.html) spawned for the DB calls it whenever a key expires.
+    ///
+    /// This method returns the number of keys that were removed, or an `anyhow::Error` if the dump
+    /// triggered by their removal fails.
+    pub fn purge_expired(&mut self) -> Result<usize> {
+        let expired = self.expired_ops(ttl::now());
+        let count = expired.len();
+        self.apply_ops(expired, true)?;
+        Ok(count)
+    }
+
+    /// Create a new list.
+    ///
+    /// This method just creates a new list, it doesn't add any elements to it.
+    /// If another list, value, set, hash or sorted set is already set under this key, they will be overridden,
+    /// meaning the new list will override the old list, value, set, hash or sorted set.
+    ///
+    /// Upon success, the method returns an object of type
+    /// [NoDbExt](struct.NoDbExt.html) that enables to add
+    /// items to the newly created list. Alternatively you can use [list_add()](#method.list_add)
+    /// or [list_extend()](#method.list_extend) to add items to the list.
+    pub fn list_create<N: AsRef<str>>(&mut self, name: N) -> Result<NoDbExt<'_>> {
+        let name = name.as_ref();
+        self.commit(vec![LogOp::ListCreate {
+            name: name.to_string(),
+        }])?;
+        Ok(NoDbExt {
+            db: self,
+            list_name: name.to_string(),
+        })
+    }
+
+    /// Check if a list exists.
+    ///
+    /// This method returns `true` if the list name exists and `false` otherwise.
+    /// The difference between this method and [exists()](#method.exists) is that this methods checks only
+    /// for lists with that name (key) and [exists()](#method.exists) checks for both values and lists.
+    pub fn list_exists<N: AsRef<str>>(&self, name: N) -> bool {
+        self.list(name.as_ref()).is_some()
+    }
+
+    /// Add a single item to an existing list.
+    ///
+    /// As mentioned before, the lists are heterogeneous, meaning a single list can contain
+    /// items of different types. That means that the item can be of any type that is serializable.
+    /// That includes all primitive types, vectors, tuples and every struct that has the
+    /// `#[derive(Serialize, Deserialize)` attribute.
+    ///
+    /// If the item was added successfully the method returns
+    /// `Some(`[NoDbExt](struct.NoDbExt.html)`)` which enables to add more
+    /// items to the list. Alternatively the method returns `None` if the list isn't found in the DB
+    /// or if a failure happened while extending the list. Failures are not likely to happen but may
+    /// occur mostly in cases where this action triggers a DB dump (which is decided according to the dump policy).
+    pub fn list_add<K: AsRef<str>, V: Serialize>(
+        &mut self,
+        name: K,
+        value: &V,
+    ) -> Option<NoDbExt<'_>> {
+        self.list_extend(name, &[value])
+    }
+
+    /// Add multiple items to an existing list.
     ///
     /// As mentioned before, the lists are heterogeneous, meaning a single list can contain
     /// items of different types. That means that the item can be of any type that is serializable.
@@ -318,195 +1603,1087 @@ impl NoDb {
     /// of them are of the same type. Of course it doesn't mean that the list cannot contain items
     /// of other types as well, as you can see in the example below.
     ///
-    /// If all items were added successfully the method returns
-    /// `Some(`[NoDbExt](struct.NoDbExt.html)`)` which enables to add more
-    /// items to the list. Alternatively the method returns `None` if the list isn't found in the DB
-    /// or if a failure happened while extending the list. Failures are not likely to happen but may
-    /// occur mostly in cases where this action triggers a DB dump (which is decided according to the dump policy).
-    pub fn list_extend<'a, N: AsRef<str>, V, I>(&mut self, name: N, seq: I) -> Option<NoDbExt>
+    /// If all items were added successfully the method returns
+    /// `Some(`[NoDbExt](struct.NoDbExt.html)`)` which enables to add more
+    /// items to the list. Alternatively the method returns `None` if the list isn't found in the DB
+    /// or if a failure happened while extending the list. Failures are not likely to happen but may
+    /// occur mostly in cases where this action triggers a DB dump (which is decided according to the dump policy).
+    pub fn list_extend<'a, N: AsRef<str>, V, I>(&mut self, name: N, seq: I) -> Option<NoDbExt<'_>>
+    where
+        V: 'a + Serialize,
+        I: IntoIterator<Item = &'a V>,
+    {
+        let name = name.as_ref();
+        if !self.list_exists(name) {
+            return None;
+        }
+        let items = seq
+            .into_iter()
+            .map(|v| self.ser.serialize_data(v).ok())
+            .collect::<Option<Vec<_>>>()?;
+        self.commit(vec![LogOp::ListExtend {
+            name: name.to_string(),
+            items,
+        }])
+        .ok()?;
+        Some(NoDbExt {
+            db: self,
+            list_name: name.to_string(),
+        })
+    }
+
+    /// Get an item of of a certain list in a certain position.
+    ///
+    /// This method takes a list name and a position inside the list
+    /// and retrieves the item in this position. It's the developer's responsibility
+    /// to know what is the correct type of the item and give it while calling this method.
+    /// Since the item in the lists are stored in a serialized way the returned object
+    /// is not a reference to the item stored in a DB but actually a new instance of it.
+    /// If the list is not found in the DB or the given position is out of bounds
+    /// of the list `None` will be returned. Otherwise `Some(V)` will be returned.
+    pub fn list_get<V: DeserializeOwned, N: AsRef<str>>(&self, name: N, pos: usize) -> Option<V> {
+        match self.list(name.as_ref()) {
+            Some(list) => match list.get(pos) {
+                Some(val) => self.ser.deserialize_data::<V>(val),
+                None => None,
+            },
+            None => None,
+        }
+    }
+
+    /// Get the length of a list.
+    ///
+    /// If the list is empty or if it doesn't exist the value of 0 is returned.
+    pub fn list_len<N: AsRef<str>>(&self, name: N) -> usize {
+        match self.list(name.as_ref()) {
+            Some(list) => list.len(),
+            None => 0,
+        }
+    }
+
+    /// Remove a list.
+    ///
+    /// This method is somewhat similar to [rem()](#method.rem) but with 2 small differences:
+    /// * This method only removes lists and not key-value pairs
+    /// * The return value of this method is the number of items that were in
+    ///   the list that was removed. If the list doesn't exist a value of zero (0) is
+    ///   returned. In case of a failure an `anyhow::Error` is returned.
+    ///   Failures are not likely to happen but may occur mostly in cases where this action triggers a
+    ///   DB dump (which is decided according to the dump policy).
+    pub fn list_rm_list<N: AsRef<str>>(&mut self, name: N) -> Result<usize> {
+        let name = name.as_ref();
+        let res = match self.list(name) {
+            Some(list) => list.len(),
+            None => return Ok(0),
+        };
+        self.commit(vec![LogOp::ListRem {
+            name: name.to_string(),
+        }])?;
+        Ok(res)
+    }
+
+    /// Pop an item out of a list.
+    ///
+    /// This method takes a list name and a position inside the list, removes the
+    /// item in this position and returns it to the user. It's the user's responsibility
+    /// to know what is the correct type of the item and give it while calling this method.
+    /// Since the item in the lists are stored in a serialized way the returned object
+    /// is not a reference to the item stored in a DB but actually a new instance of it.
+    ///
+    /// If the list is not found in the DB or the given position is out of bounds no item
+    /// will be removed and `None` will be returned. `None` may also be returned
+    /// if removing the item fails, which may happen mostly in cases where this action
+    /// triggers a DB dump (which is decided according to the dump policy).
+    /// Otherwise the item will be removed and `Some(V)` will be returned.
+    ///
+    /// This method is very similar to [list_rm_val()](#method.list_rm_val), the only difference is that this
+    /// methods returns the value and [list_rm_val()](#method.list_rm_val) returns only an indication whether
+    /// the item was removed or not.
+    pub fn list_pop<V: DeserializeOwned, N: AsRef<str>>(
+        &mut self,
+        name: N,
+        pos: usize,
+    ) -> Option<V> {
+        let name = name.as_ref();
+        let res = self.list(name)?.get(pos)?.clone();
+        self.commit(vec![LogOp::ListRemove {
+            name: name.to_string(),
+            pos,
+        }])
+        .ok()?;
+        self.ser.deserialize_data::<V>(&res)
+    }
+
+    /// Remove an item out of a list.
+    ///
+    /// This method takes a list name and a reference to a value, removes the first instance of the
+    /// value if it exists in the list, and returns an indication whether the item was removed or not.
+    ///
+    /// If the list is not found in the DB or the given value isn't found in the list, no item will
+    /// be removed and `Ok(false)` will be returned.
+    /// If removing the item fails, which may happen mostly in cases where this action triggers
+    /// a DB dump (which is decided according to the dump policy), an
+    /// `anyhow::Error` is returned. Otherwise the item will be removed and `Ok(true)` will be returned.
+    ///
+    /// This method is very similar to [list_pop()](#method.list_pop), the only difference is that this
+    /// methods returns an indication and [list_pop()](#method.list_pop) returns the actual item that was removed.
+    pub fn list_rm_val<V: Serialize, N: AsRef<str>>(&mut self, name: N, value: &V) -> Result<bool> {
+        let name = name.as_ref();
+        let list = match self.list(name) {
+            Some(list) => list,
+            None => return Ok(false),
+        };
+        let serialized_value = match self.ser.serialize_data(&value) {
+            Ok(val) => val,
+            Err(err) => {
+                return Err(anyhow!(
+                    "Error serializing value: {}",
+                    err.to_string().replace('\n', "")
+                ))
+            }
+        };
+        match list.iter().position(|x| *x == serialized_value) {
+            Some(pos) => {
+                self.commit(vec![LogOp::ListRemove {
+                    name: name.to_string(),
+                    pos,
+                }])?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Add a member to a set.
+    ///
+    /// Sets hold unique members, and tell whether they hold a member in constant time, where lists have
+    /// to be searched through. Members are compared by their serialized data. Like lists, sets are
+    /// heterogeneous: a single set can hold members of different types, of any type that is serializable.
+    /// The set is created if it doesn't exist yet.
+    ///
+    /// This method returns `Ok(true)` if the member was added and `Ok(false)` if the set already holds it.
+    /// An `anyhow::Error` is returned if the key holds anything but a set, if the member can't be serialized,
+    /// or if the dump triggered by this change fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// assert!(db.set_add("tags", &"rust").unwrap());
+    /// assert!(db.set_add("tags", &"db").unwrap());
+    /// // already in the set
+    /// assert!(!db.set_add("tags", &"rust").unwrap());
+    ///
+    /// assert!(db.set_contains("tags", &"db"));
+    /// assert_eq!(db.set_len("tags"), 2);
+    /// ```
+    pub fn set_add<N: AsRef<str>, V: Serialize>(&mut self, name: N, value: &V) -> Result<bool> {
+        let name = name.as_ref();
+        match self.held(name) {
+            None | Some(Held::Set) => {}
+            Some(held) => return Err(anyhow!("{} holds {}, not a set", name, held)),
+        }
+        let member = self.ser.serialize_data(value)?;
+        if self.members(name).is_some_and(|set| set.contains(&member)) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::SetAdd {
+            name: name.to_string(),
+            members: vec![member],
+        }])?;
+        Ok(true)
+    }
+
+    /// Remove a member from a set.
+    ///
+    /// The set is removed once its last member is removed.
+    ///
+    /// This method returns `Ok(true)` if the member was removed and `Ok(false)` if the set isn't found or
+    /// doesn't hold it. An `anyhow::Error` is returned if the member can't be serialized, or if the dump
+    /// triggered by this change fails.
+    pub fn set_rem<N: AsRef<str>, V: Serialize>(&mut self, name: N, value: &V) -> Result<bool> {
+        let name = name.as_ref();
+        let member = self.ser.serialize_data(value)?;
+        if !self.members(name).is_some_and(|set| set.contains(&member)) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::SetRem {
+            name: name.to_string(),
+            members: vec![member],
+        }])?;
+        Ok(true)
+    }
+
+    /// Check if a set holds a member.
+    ///
+    /// This method returns `false` if the set isn't found or if the member can't be serialized.
+    pub fn set_contains<N: AsRef<str>, V: Serialize>(&self, name: N, value: &V) -> bool {
+        match (self.members(name.as_ref()), self.ser.serialize_data(value)) {
+            (Some(set), Ok(member)) => set.contains(&member),
+            _ => false,
+        }
+    }
+
+    /// Get the members of a set, in no particular order.
+    ///
+    /// It's the developer's responsibility to know the type of the members. Members that can't be
+    /// deserialized to that type are left out. If the set isn't found an empty vector is returned.
+    pub fn set_members<V: DeserializeOwned, N: AsRef<str>>(&self, name: N) -> Vec<V> {
+        self.members(name.as_ref())
+            .map_or_else(Vec::new, |set| self.deserialize_members(set))
+    }
+
+    /// Get the number of members of a set.
+    ///
+    /// If the set doesn't exist the value of 0 is returned.
+    pub fn set_len<N: AsRef<str>>(&self, name: N) -> usize {
+        self.members(name.as_ref()).map_or(0, HashSet::len)
+    }
+
+    /// Get the members that are in any of the given sets, in no particular order.
+    ///
+    /// Sets that aren't found are treated as empty sets. Members are deserialized as with
+    /// [set_members()](#method.set_members).
+    pub fn set_union<V: DeserializeOwned, N: AsRef<str>>(&self, names: &[N]) -> Vec<V> {
+        let members = names
+            .iter()
+            .filter_map(|name| self.members(name.as_ref()))
+            .flatten()
+            .collect::<HashSet<_>>();
+        self.deserialize_members(members)
+    }
+
+    /// Get the members that are in all of the given sets, in no particular order.
+    ///
+    /// Sets that aren't found are treated as empty sets, so the result is empty if any of them isn't
+    /// found. Members are deserialized as with [set_members()](#method.set_members).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// for user in ["alice", "bob", "carol"] {
+    ///     db.set_add("readers", &user).unwrap();
+    /// }
+    /// for user in ["bob", "dave"] {
+    ///     db.set_add("writers", &user).unwrap();
+    /// }
+    ///
+    /// let both = db.set_intersection::<String, _>(&["readers", "writers"]);
+    /// assert_eq!(both, vec!["bob".to_string()]);
+    /// let readers_only = db.set_difference::<String, _>(&["readers", "writers"]);
+    /// assert_eq!(readers_only.len(), 2);
+    /// ```
+    pub fn set_intersection<V: DeserializeOwned, N: AsRef<str>>(&self, names: &[N]) -> Vec<V> {
+        let sets = names
+            .iter()
+            .map(|name| self.members(name.as_ref()))
+            .collect::<Option<Vec<_>>>()
+            .unwrap_or_default();
+        match sets.iter().min_by_key(|set| set.len()) {
+            Some(smallest) => self.deserialize_members(
+                smallest
+                    .iter()
+                    .filter(|member| sets.iter().all(|set| set.contains(*member))),
+            ),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the members of the first of the given sets that aren't in any of the others, in no particular
+    /// order.
+    ///
+    /// Sets that aren't found are treated as empty sets. Members are deserialized as with
+    /// [set_members()](#method.set_members).
+    pub fn set_difference<V: DeserializeOwned, N: AsRef<str>>(&self, names: &[N]) -> Vec<V> {
+        let (first, others) = match names.split_first() {
+            Some((first, others)) => (first, others),
+            None => return Vec::new(),
+        };
+        let others = others
+            .iter()
+            .filter_map(|name| self.members(name.as_ref()))
+            .collect::<Vec<_>>();
+        match self.members(first.as_ref()) {
+            Some(set) => self.deserialize_members(
+                set.iter()
+                    .filter(|member| !others.iter().any(|other| other.contains(*member))),
+            ),
+            None => Vec::new(),
+        }
+    }
+
+    /// Deserializes the members of a set, leaving out the ones that can't be deserialized.
+    fn deserialize_members<'a, V, I>(&self, members: I) -> Vec<V>
     where
-        V: 'a + Serialize,
-        I: IntoIterator<Item = &'a V>,
+        V: DeserializeOwned,
+        I: IntoIterator<Item = &'a Vec<u8>>,
     {
-        let ser = &self.ser;
-        match self.list_map.get_mut(name.as_ref()) {
-            Some(list) => {
-                let orig_len = list.len();
-                let serialized = seq
-                    .into_iter()
-                    .map(|v| ser.serialize_data(v).ok())
-                    .collect::<Option<Vec<_>>>()?;
-
-                list.extend(serialized);
-
-                if self.dumpdb().is_err() {
-                    let same_list = self.list_map.get_mut(name.as_ref())?;
-                    same_list.truncate(orig_len);
-                    return None;
-                }
+        members
+            .into_iter()
+            .filter_map(|member| self.ser.deserialize_data(member))
+            .collect()
+    }
+
+    /// Set a field of a hash.
+    ///
+    /// Hashes map the names of their fields to values. Each value is serialized on its own, so that a
+    /// single field can be read or changed without reading or rewriting the whole hash, e.g. to store the
+    /// fields of a struct that change often. Like lists, hashes are heterogeneous: each field can hold a
+    /// value of any type that is serializable. The hash is created if it doesn't exist yet, and the value
+    /// of the field is overridden if it's already set.
+    ///
+    /// This method returns `Ok(())` if set is successful. An `anyhow::Error` is returned if the key holds
+    /// anything but a hash, if the value can't be serialized, or if the dump triggered by this change fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.hset("user:1", "name", "alice").unwrap();
+    /// db.hset("user:1", "age", 30).unwrap();
+    ///
+    /// // only the age is written
+    /// db.hincr_by("user:1", "age", 1).unwrap();
+    /// assert_eq!(db.hget::<u32, _, _>("user:1", "age"), Some(31));
+    /// assert_eq!(db.hget::<String, _, _>("user:1", "name").unwrap(), "alice");
+    /// ```
+    pub fn hset<K, F, V>(&mut self, key: K, field: F, value: V) -> Result<()>
+    where
+        K: AsRef<str>,
+        F: AsRef<str>,
+        V: Serialize,
+    {
+        let key = key.as_ref();
+        match self.held(key) {
+            None | Some(Held::Hash) => {}
+            Some(held) => return Err(anyhow!("{} holds {}, not a hash", key, held)),
+        }
+        let value = self.ser.serialize_data(&value)?;
+        self.commit(vec![LogOp::HashPut {
+            name: key.to_string(),
+            fields: vec![(field.as_ref().to_string(), value)],
+        }])
+    }
+
+    /// Get the value of a field of a hash.
+    ///
+    /// It's the developer's responsibility to know the type of the value. If the hash or the field
+    /// doesn't exist or if the type is wrong, `None` will be returned. Otherwise `Some(V)` will be returned.
+    pub fn hget<V: DeserializeOwned, K: AsRef<str>, F: AsRef<str>>(
+        &self,
+        key: K,
+        field: F,
+    ) -> Option<V> {
+        self.ser
+            .deserialize_data(self.hash(key.as_ref())?.get(field.as_ref())?)
+    }
+
+    /// Remove a field from a hash.
+    ///
+    /// The hash is removed once its last field is removed.
+    ///
+    /// This method returns `Ok(true)` if the field was removed and `Ok(false)` if the hash or the field
+    /// isn't found. An `anyhow::Error` is returned if the dump triggered by this change fails.
+    pub fn hdel<K: AsRef<str>, F: AsRef<str>>(&mut self, key: K, field: F) -> Result<bool> {
+        let (key, field) = (key.as_ref(), field.as_ref());
+        if !self.hash(key).is_some_and(|hash| hash.contains_key(field)) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::HashDel {
+            name: key.to_string(),
+            fields: vec![field.to_string()],
+        }])?;
+        Ok(true)
+    }
+
+    /// Get the names of the fields of a hash, in no particular order.
+    ///
+    /// If the hash doesn't exist an empty vector is returned.
+    pub fn hkeys<K: AsRef<str>>(&self, key: K) -> Vec<String> {
+        self.hash(key.as_ref())
+            .map_or_else(Vec::new, |hash| hash.keys().cloned().collect())
+    }
+
+    /// Get the fields of a hash along with their values.
+    ///
+    /// It's the developer's responsibility to know the type of the values. Fields whose value can't be
+    /// deserialized to that type are left out. If the hash doesn't exist an empty map is returned.
+    pub fn hgetall<V: DeserializeOwned, K: AsRef<str>>(&self, key: K) -> HashMap<String, V> {
+        self.hash(key.as_ref()).map_or_else(HashMap::new, |hash| {
+            hash.iter()
+                .filter_map(|(field, value)| {
+                    Some((field.clone(), self.ser.deserialize_data(value)?))
+                })
+                .collect()
+        })
+    }
+
+    /// Increment the integer value of a field of a hash.
+    ///
+    /// The value is read as an `i64`, incremented and set back with a single dump, as with
+    /// [incr_by()](#method.incr_by). If the field doesn't exist, it is set to `by` as if its value was 0,
+    /// and the hash is created if needed.
+    ///
+    /// This method returns `Ok(value)` with the new value of the field. An `anyhow::Error` is returned and
+    /// the value is left untouched if it isn't an integer, if the key holds anything but a hash, if the
+    /// result overflows an `i64`, or if the dump fails.
+    pub fn hincr_by<K: AsRef<str>, F: AsRef<str>>(
+        &mut self,
+        key: K,
+        field: F,
+        by: i64,
+    ) -> Result<i64> {
+        let (key, field) = (key.as_ref(), field.as_ref());
+        match self.held(key) {
+            None | Some(Held::Hash) => {}
+            Some(held) => return Err(anyhow!("{} holds {}, not a hash", key, held)),
+        }
+        let value = match self.hash(key).and_then(|hash| hash.get(field)) {
+            Some(value) => self
+                .number::<i64>(value)
+                .ok_or_else(|| anyhow!("Field {} of {} doesn't hold a number", field, key))?
+                .checked_add(by)
+                .ok_or_else(|| anyhow!("Incrementing field {} of {} overflows", field, key))?,
+            None => by,
+        };
+        self.commit(vec![LogOp::HashPut {
+            name: key.to_string(),
+            fields: vec![(field.to_string(), self.ser.serialize_data(&value)?)],
+        }])?;
+        Ok(value)
+    }
+
+    /// Add a member to a sorted set, or change the score of a member it already holds.
+    ///
+    /// Like sets, sorted sets hold unique members, but each member has an `f64` score and the members are
+    /// ordered by their score, then by their serialized data for equal scores. They can be read by rank or
+    /// by score, e.g. to keep a leaderboard or a priority queue. Adding, removing or rescoring a member
+    /// takes a logarithmic time. The sorted set is created if it doesn't exist yet.
+    ///
+    /// This method returns `Ok(true)` if the member was added and `Ok(false)` if the sorted set already
+    /// holds it, in which case only its score is changed. An `anyhow::Error` is returned if the key holds
+    /// anything but a sorted set, if the score isn't finite, if the member can't be serialized, or if the
+    /// dump triggered by this change fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.zadd("scores", &"alice", 42.0).unwrap();
+    /// db.zadd("scores", &"bob", 17.0).unwrap();
+    /// db.zadd("scores", &"carol", 99.5).unwrap();
+    ///
+    /// assert_eq!(db.zrank("scores", &"alice"), Some(1));
+    /// assert_eq!(db.zrange_by_score::<String, _, _>("scores", 20.0..).len(), 2);
+    ///
+    /// // the best player
+    /// let (name, score) = db.zpop_max::<String, _>("scores").unwrap().unwrap();
+    /// assert_eq!((name.as_str(), score), ("carol", 99.5));
+    /// ```
+    pub fn zadd<K: AsRef<str>, V: Serialize>(
+        &mut self,
+        key: K,
+        member: &V,
+        score: f64,
+    ) -> Result<bool> {
+        let key = key.as_ref();
+        let member = self.zset_member(key, member)?;
+        if !score.is_finite() {
+            return Err(anyhow!(
+                "Score {} of a member of {} isn't finite",
+                score,
+                key
+            ));
+        }
+        let previous = self.zset(key).and_then(|zset| zset.score(&member));
+        if previous == Some(score) {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::ZSetAdd {
+            name: key.to_string(),
+            members: vec![(member, score)],
+        }])?;
+        Ok(previous.is_none())
+    }
+
+    /// Increment the score of a member of a sorted set.
+    ///
+    /// If the member isn't in the sorted set, it is added with a score of `by` as if its score was 0, and
+    /// the sorted set is created if needed.
+    ///
+    /// This method returns `Ok(score)` with the new score of the member. An `anyhow::Error` is returned and
+    /// the score is left untouched if the key holds anything but a sorted set, if the new score isn't
+    /// finite, if the member can't be serialized, or if the dump fails.
+    pub fn zincr_by<K: AsRef<str>, V: Serialize>(
+        &mut self,
+        key: K,
+        member: &V,
+        by: f64,
+    ) -> Result<f64> {
+        let key = key.as_ref();
+        let member = self.zset_member(key, member)?;
+        let score = self
+            .zset(key)
+            .and_then(|zset| zset.score(&member))
+            .unwrap_or(0.0)
+            + by;
+        if !score.is_finite() {
+            return Err(anyhow!(
+                "Incrementing a member of {} results in {}",
+                key,
+                score
+            ));
+        }
+        self.commit(vec![LogOp::ZSetAdd {
+            name: key.to_string(),
+            members: vec![(member, score)],
+        }])?;
+        Ok(score)
+    }
+
+    /// Remove a member from a sorted set.
+    ///
+    /// The sorted set is removed once its last member is removed.
+    ///
+    /// This method returns `Ok(true)` if the member was removed and `Ok(false)` if the sorted set or the
+    /// member isn't found. An `anyhow::Error` is returned if the member can't be serialized, or if the dump
+    /// triggered by this change fails.
+    pub fn zrem<K: AsRef<str>, V: Serialize>(&mut self, key: K, member: &V) -> Result<bool> {
+        let key = key.as_ref();
+        let member = self.ser.serialize_data(member)?;
+        if self
+            .zset(key)
+            .and_then(|zset| zset.score(&member))
+            .is_none()
+        {
+            return Ok(false);
+        }
+        self.commit(vec![LogOp::ZSetRem {
+            name: key.to_string(),
+            members: vec![member],
+        }])?;
+        Ok(true)
+    }
+
+    /// Get the score of a member of a sorted set.
+    ///
+    /// This method returns `None` if the sorted set or the member isn't found, or if the member can't be
+    /// serialized.
+    pub fn zscore<K: AsRef<str>, V: Serialize>(&self, key: K, member: &V) -> Option<f64> {
+        let member = self.ser.serialize_data(member).ok()?;
+        self.zset(key.as_ref())?.score(&member)
+    }
+
+    /// Get the rank of a member of a sorted set, the member with the lowest score having the rank 0.
+    ///
+    /// Unlike the other sorted set operations, this takes a time proportional to the rank of the member.
+    ///
+    /// This method returns `None` if the sorted set or the member isn't found, or if the member can't be
+    /// serialized.
+    pub fn zrank<K: AsRef<str>, V: Serialize>(&self, key: K, member: &V) -> Option<usize> {
+        let member = self.ser.serialize_data(member).ok()?;
+        self.zset(key.as_ref())?.rank(&member)
+    }
+
+    /// Get the number of members of a sorted set.
+    ///
+    /// If the sorted set doesn't exist the value of 0 is returned.
+    pub fn zlen<K: AsRef<str>>(&self, key: K) -> usize {
+        self.zset(key.as_ref()).map_or(0, SortedSet::len)
+    }
+
+    /// Get the members of a sorted set whose rank is within a range, along with their scores.
+    ///
+    /// Members are returned from the lowest score to the highest, e.g. `0..10` returns the 10 members
+    /// with the lowest scores. Members that can't be deserialized to `V` are left out. If the sorted set
+    /// isn't found an empty vector is returned.
+    ///
+    /// This takes a time proportional to the end of the range, as the members ranked before its start are
+    /// skipped one by one.
+    pub fn zrange_by_rank<V, K, R>(&self, key: K, range: R) -> Vec<(V, f64)>
+    where
+        V: DeserializeOwned,
+        K: AsRef<str>,
+        R: RangeBounds<usize>,
+    {
+        match self.zset(key.as_ref()) {
+            Some(zset) => self.deserialize_scored(zset.range_by_rank(range)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get the members of a sorted set whose score is within a range, along with their scores.
+    ///
+    /// Members are returned from the lowest score to the highest. Members that can't be deserialized to
+    /// `V` are left out. If the sorted set isn't found an empty vector is returned.
+    pub fn zrange_by_score<V, K, R>(&self, key: K, range: R) -> Vec<(V, f64)>
+    where
+        V: DeserializeOwned,
+        K: AsRef<str>,
+        R: RangeBounds<f64>,
+    {
+        match self.zset(key.as_ref()) {
+            Some(zset) => self.deserialize_scored(zset.range_by_score(range)),
+            None => Vec::new(),
+        }
+    }
+
+    /// Remove the member with the lowest score from a sorted set.
+    ///
+    /// The sorted set is removed once its last member is removed.
+    ///
+    /// This method returns `Ok(Some((member, score)))` with the removed member and its score, or `Ok(None)`
+    /// if the sorted set isn't found. An `anyhow::Error` is returned and the member is left in the sorted
+    /// set if it can't be deserialized to `V`, or if the dump triggered by this change fails.
+    pub fn zpop_min<V: DeserializeOwned, K: AsRef<str>>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<(V, f64)>> {
+        let key = key.as_ref();
+        let first = self.zset(key).and_then(|zset| zset.iter().next());
+        self.zpop(key, first.map(|(member, score)| (member.to_vec(), score)))
+    }
+
+    /// Remove the member with the highest score from a sorted set.
+    ///
+    /// This method behaves like [zpop_min()](#method.zpop_min), from the other end of the sorted set.
+    pub fn zpop_max<V: DeserializeOwned, K: AsRef<str>>(
+        &mut self,
+        key: K,
+    ) -> Result<Option<(V, f64)>> {
+        let key = key.as_ref();
+        let last = self.zset(key).and_then(|zset| zset.iter().next_back());
+        self.zpop(key, last.map(|(member, score)| (member.to_vec(), score)))
+    }
+
+    /// Removes a member popped from a sorted set.
+    fn zpop<V: DeserializeOwned>(
+        &mut self,
+        key: &str,
+        popped: Option<(Vec<u8>, f64)>,
+    ) -> Result<Option<(V, f64)>> {
+        let (member, score) = match popped {
+            Some(popped) => popped,
+            None => return Ok(None),
+        };
+        let value = self
+            .ser
+            .deserialize_data(&member)
+            .ok_or_else(|| anyhow!("Failed to deserialize a member of {}", key))?;
+        self.commit(vec![LogOp::ZSetRem {
+            name: key.to_string(),
+            members: vec![member],
+        }])?;
+        Ok(Some((value, score)))
+    }
+
+    /// Checks that members can be added to the sorted set under a key, and serializes a member.
+    fn zset_member<V: Serialize>(&self, key: &str, member: &V) -> Result<Vec<u8>> {
+        match self.held(key) {
+            None | Some(Held::ZSet) => {}
+            Some(held) => return Err(anyhow!("{} holds {}, not a sorted set", key, held)),
+        }
+        self.ser.serialize_data(member)
+    }
+
+    /// Deserializes the members of a sorted set along with their scores, leaving out the members that
+    /// can't be deserialized.
+    fn deserialize_scored<'a, V, I>(&self, members: I) -> Vec<(V, f64)>
+    where
+        V: DeserializeOwned,
+        I: Iterator<Item = (&'a [u8], f64)>,
+    {
+        members
+            .filter_map(|(member, score)| Some((self.ser.deserialize_data(member)?, score)))
+            .collect()
+    }
+
+    /// Run several changes in a transaction.
+    ///
+    /// The closure is given a [Transaction](struct.Transaction.html) through which it can set and
+    /// remove values and lists, and read them back with its own changes applied. The changes are
+    /// staged until the closure returns:
+    /// * If the closure returns `Ok`, the changes are committed: they are all applied to the DB and
+    ///   dumped at once according to the dump policy. With a write-ahead log, they are appended as a
+    ///   single record so that they are replayed either all or not at all.
+    /// * If the closure returns an `anyhow::Error`, the transaction is rolled back: none of the changes
+    ///   are applied and the error is returned.
+    ///
+    /// If the dump fails, the DB is left as it was before the transaction and the error is returned.
+    /// Otherwise the value returned by the closure is returned.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// db.set("alice", 100).unwrap();
+    /// db.set("bob", 50).unwrap();
+    ///
+    /// // move 30 from alice to bob, or nothing at all
+    /// db.transaction(|tx| {
+    ///     let alice = tx.get::<_, i32>("alice").unwrap_or(0);
+    ///     let bob = tx.get::<_, i32>("bob").unwrap_or(0);
+    ///     if alice < 30 {
+    ///         anyhow::bail!("insufficient funds");
+    ///     }
+    ///     tx.set("alice", alice - 30)?;
+    ///     tx.set("bob", bob + 30)?;
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn transaction<T, F>(&mut self, f: F) -> Result<T>
+    where
+        F: FnOnce(&mut Transaction<'_>) -> Result<T>,
+    {
+        let mut tx = Transaction::new(
+            &self.map,
+            &self.list_map,
+            &self.set_map,
+            &self.hash_map,
+            &self.zset_map,
+            &self.expires,
+            &self.ser,
+        );
+        let res = f(&mut tx)?;
+        let ops = tx.ops;
+        self.commit(ops)?;
+        Ok(res)
+    }
+
+    /// Create an empty batch of changes for the DB.
+    ///
+    /// Changes added to the [WriteBatch](struct.WriteBatch.html) are applied to the DB at once with
+    /// [write_batch()](#method.write_batch).
+    pub fn batch(&self) -> WriteBatch {
+        WriteBatch::new(self.ser.method())
+    }
+
+    /// Apply a batch of changes to the DB.
+    ///
+    /// The changes are applied in the order they were added to the batch, then dumped at once according
+    /// to the dump policy, so that loading many values with
+    /// [DumpPolicy::Auto](enum.DumpPolicy.html#variant.Auto) doesn't dump the DB once per value.
+    ///
+    /// Either all the changes are applied or none of them are: an `anyhow::Error` is returned and the DB is
+    /// left untouched if the batch was created for a DB with another serialization method, if it adds
+    /// items to a list that doesn't exist, or if the dump fails.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    ///
+    /// let mut batch = db.batch();
+    /// for i in 0..50 {
+    ///     batch.set(format!("key{}", i), i).unwrap();
+    /// }
+    /// batch.list_create("list1").list_add("list1", &100).unwrap();
+    ///
+    /// // a single dump for all the changes
+    /// db.write_batch(batch).unwrap();
+    /// ```
+    pub fn write_batch(&mut self, batch: WriteBatch) -> Result<()> {
+        if batch.ser.method() != self.ser.method() {
+            return Err(anyhow!(
+                "The batch was created for {}, the DB uses {}",
+                batch.ser.method(),
+                self.ser.method()
+            ));
+        }
 
-                Some(NoDbExt {
-                    db: self,
-                    list_name: name.as_ref().to_string(),
-                })
+        let mut lists = HashMap::new();
+        for op in batch.ops.iter() {
+            match op {
+                LogOp::Set { key: name, .. }
+                | LogOp::Rem { key: name }
+                | LogOp::ListRem { name } => {
+                    lists.insert(name.as_str(), false);
+                }
+                LogOp::ListCreate { name } => {
+                    lists.insert(name.as_str(), true);
+                }
+                LogOp::ListExtend { name, .. } => {
+                    let exists = lists
+                        .get(name.as_str())
+                        .copied()
+                        .unwrap_or_else(|| self.list(name).is_some());
+                    if !exists {
+                        return Err(anyhow!("List {} doesn't exist", name));
+                    }
+                }
+                _ => {}
             }
-            None => None,
         }
+        self.commit(batch.ops)
     }
 
-    /// Get an item of of a certain list in a certain position.
+    /// Take a snapshot of the DB.
     ///
-    /// This method takes a list name and a position inside the list
-    /// and retrieves the item in this position. It's the developer's responsibility
-    /// to know what is the correct type of the item and give it while calling this method.
-    /// Since the item in the lists are stored in a serialized way the returned object
-    /// is not a reference to the item stored in a DB but actually a new instance of it.
-    /// If the list is not found in the DB or the given position is out of bounds
-    /// of the list `None` will be returned. Otherwise `Some(V)` will be returned.
-    pub fn list_get<V: DeserializeOwned, N: AsRef<str>>(&self, name: N, pos: usize) -> Option<V> {
-        match self.list_map.get(name.as_ref()) {
-            Some(list) => match list.get(pos) {
-                Some(val) => self.ser.deserialize_data::<V>(val),
-                None => None,
-            },
-            None => None,
+    /// The [NoDbSnapshot](struct.NoDbSnapshot.html) holds the values, lists, sets, hashes and sorted sets of the DB at the
+    /// time of the call, that can be read, e.g. from another thread, while the DB keeps being changed.
+    /// Taking the snapshot doesn't copy anything: the snapshot shares the maps of the DB, so a DB shared
+    /// behind a lock is released right away rather than for as long as the snapshot is read. The first
+    /// change made to the DB while a snapshot still shares its maps copies them instead.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use std::{sync::{Arc, Mutex}, thread};
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let db = NoDb::new("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// let db = Arc::new(Mutex::new(db));
+    ///
+    /// let snapshot = db.lock().unwrap().snapshot();
+    /// let report = thread::spawn(move || {
+    ///     for kv in snapshot.iter() {
+    ///         println!("{}", kv.get_key());
+    ///     }
+    /// });
+    ///
+    /// // the DB can be written while the report is running
+    /// db.lock().unwrap().set("key", 100).unwrap();
+    /// report.join().unwrap();
+    /// ```
+    pub fn snapshot(&self) -> NoDbSnapshot {
+        let data = SharedData {
+            map: Arc::clone(&self.map),
+            list_map: Arc::clone(&self.list_map),
+            set_map: Arc::clone(&self.set_map),
+            hash_map: Arc::clone(&self.hash_map),
+            zset_map: Arc::clone(&self.zset_map),
+            expires: Arc::clone(&self.expires),
+        };
+        NoDbSnapshot::new(data, self.ser.method(), self.key_index.is_some())
+    }
+
+    /// Return an iterator over the keys and values in the DB.
+    pub fn iter(&self) -> NoDbIter<'_> {
+        if self.key_index.is_some() {
+            return self.range_iter((Bound::Unbounded, Bound::Unbounded), false);
+        }
+        NoDbIter {
+            map_iter: MapIter::Unordered(self.map.iter()),
+            ser: &self.ser,
+            expires: &self.expires,
+            now: ttl::now(),
         }
     }
 
-    /// Get the length of a list.
+    /// Return an iterator over the keys and values in the DB whose keys are within a range, in key order.
     ///
-    /// If the list is empty or if it doesn't exist the value of 0 is returned.
-    pub fn list_len<N: AsRef<str>>(&self, name: N) -> usize {
-        match self.list_map.get(name.as_ref()) {
-            Some(list) => list.len(),
-            None => 0,
-        }
+    /// Keys are compared as strings, e.g. the range `"user_100".."user_200"` holds `user_150` but also
+    /// `user_1000`. If the keys are indexed, see [with_ordered_keys()](#method.with_ordered_keys), the
+    /// start of the range is found in the index. Otherwise every key of the DB is read, and the keys within
+    /// the range are sorted before the iteration starts.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let db = NoDb::load("example.db", DumpPolicy::Auto, SerializationMethod::Json)
+    ///     .unwrap()
+    ///     .with_ordered_keys();
+    /// for kv in db.range("user_100".."user_200") {
+    ///     println!("{}: {:?}", kv.get_key(), kv.get_value::<String>());
+    /// }
+    /// ```
+    pub fn range<K: AsRef<str>, R: RangeBounds<K>>(&self, range: R) -> NoDbIter<'_> {
+        self.range_iter(str_bounds(&range), false)
     }
 
-    /// Remove a list.
+    /// Return an iterator over the keys and values in the DB whose keys are within a range, in reverse key
+    /// order.
     ///
-    /// This method is somewhat similar to [rem()](#method.rem) but with 2 small differences:
-    /// * This method only removes lists and not key-value pairs
-    /// * The return value of this method is the number of items that were in
-    ///   the list that was removed. If the list doesn't exist a value of zero (0) is
-    ///   returned. In case of a failure an `anyhow::Error` is returned.
-    ///   Failures are not likely to happen but may occur mostly in cases where this action triggers a
-    ///   DB dump (which is decided according to the dump policy).
-    pub fn list_rm_list<N: AsRef<str>>(&mut self, name: N) -> Result<usize> {
-        let res = self.list_len(&name);
-        let name = name.as_ref();
-        match self.list_map.remove(name) {
-            Some(list) => match self.dumpdb() {
-                Ok(_) => Ok(res),
-                Err(err) => {
-                    self.list_map.insert(String::from(name), list);
-                    Err(err)
-                }
+    /// This method behaves like [range()](#method.range), starting from the end of the range.
+    pub fn range_rev<K: AsRef<str>, R: RangeBounds<K>>(&self, range: R) -> NoDbIter<'_> {
+        self.range_iter(str_bounds(&range), true)
+    }
+
+    fn range_iter(&self, bounds: (Bound<&str>, Bound<&str>), rev: bool) -> NoDbIter<'_> {
+        let map_iter = match &self.key_index {
+            Some(_) if is_empty_range(bounds) => MapIter::Sorted(Vec::new().into_iter()),
+            Some(index) => MapIter::Indexed {
+                keys: index.range::<str, _>(bounds),
+                map: &self.map,
+                rev,
             },
-            None => Ok(res),
+            None => {
+                let mut entries = self
+                    .map
+                    .iter()
+                    .filter(|(key, _)| RangeBounds::<str>::contains(&bounds, key.as_str()))
+                    .collect::<Vec<_>>();
+                entries.sort_unstable_by_key(|(key, _)| *key);
+                if rev {
+                    entries.reverse();
+                }
+                MapIter::Sorted(entries.into_iter())
+            }
+        };
+        NoDbIter {
+            map_iter,
+            ser: &self.ser,
+            expires: &self.expires,
+            now: ttl::now(),
         }
     }
 
-    /// Pop an item out of a list.
+    /// Return an iterator over the values and lists in the DB whose keys start with a prefix, in key order.
     ///
-    /// This method takes a list name and a position inside the list, removes the
-    /// item in this position and returns it to the user. It's the user's responsibility
-    /// to know what is the correct type of the item and give it while calling this method.
-    /// Since the item in the lists are stored in a serialized way the returned object
-    /// is not a reference to the item stored in a DB but actually a new instance of it.
+    /// Each item tells whether its key holds a value or a list. Keys holding a set, a hash or a sorted set
+    /// are left out. If the keys are indexed, see [with_ordered_keys()](#method.with_ordered_keys), the
+    /// first key is found in the index and the following ones are read as the iteration goes. Otherwise
+    /// every key of the DB is read, and the matching keys are sorted before the iteration starts.
     ///
-    /// If the list is not found in the DB or the given position is out of bounds no item
-    /// will be removed and `None` will be returned. `None` may also be returned
-    /// if removing the item fails, which may happen mostly in cases where this action
-    /// triggers a DB dump (which is decided according to the dump policy).
-    /// Otherwise the item will be removed and `Some(V)` will be returned.
+    /// # Examples
     ///
-    /// This method is very similar to [list_rm_val()](#method.list_rm_val), the only difference is that this
-    /// methods returns the value and [list_rm_val()](#method.list_rm_val) returns only an indication whether
-    /// the item was removed or not.
-    pub fn list_pop<V: DeserializeOwned, N: AsRef<str>>(
-        &mut self,
-        name: N,
-        pos: usize,
-    ) -> Option<V> {
-        let name = name.as_ref();
-        match self.list_map.get_mut(name) {
-            Some(list) => {
-                if pos < list.len() {
-                    let res = list.remove(pos);
-                    match self.dumpdb() {
-                        Ok(_) => self.ser.deserialize_data::<V>(&res),
-                        Err(_) => {
-                            let same_list = self.list_map.get_mut(name).unwrap();
-                            same_list.insert(pos, res);
-                            None
-                        }
-                    }
-                } else {
-                    None
-                }
-            }
-            None => None,
-        }
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let db = NoDb::load("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// for item in db.scan_prefix("user_") {
+    ///     if item.is_list() {
+    ///         println!("{}: {} items", item.get_key(), item.list_iter().count());
+    ///     } else {
+    ///         println!("{}: {:?}", item.get_key(), item.get_value::<String>());
+    ///     }
+    /// }
+    /// ```
+    pub fn scan_prefix<P: AsRef<str>>(&self, prefix: P) -> NoDbScanIter<'_> {
+        self.scan(prefix.as_ref().to_string(), |_| true)
     }
 
-    /// Remove an item out of a list.
+    /// Return an iterator over the values and lists in the DB whose keys match a glob pattern, in key
+    /// order.
     ///
-    /// This method takes a list name and a reference to a value, removes the first instance of the
-    /// value if it exists in the list, and returns an indication whether the item was removed or not.
+    /// Patterns are written as in Redis: `*` matches any sequence of characters, `?` any single character,
+    /// `[abc]` one of the characters between the brackets, `[a-z]` one of a range of characters and
+    /// `[^abc]` any character but the ones between the brackets, while `\` escapes the character that
+    /// follows it. For example `user_*` matches every key starting with `user_`. Otherwise this method
+    /// behaves like [scan_prefix()](#method.scan_prefix).
+    pub fn keys_matching<P: AsRef<str>>(&self, pattern: P) -> NoDbScanIter<'_> {
+        let pattern = pattern.as_ref().to_string();
+        self.scan(glob::literal_prefix(&pattern).to_string(), move |key| {
+            glob::matches(&pattern, key)
+        })
+    }
+
+    /// Remove every value and list whose key starts with a prefix from the DB.
     ///
-    /// If the list is not found in the DB or the given value isn't found in the list, no item will
-    /// be removed and `Ok(false)` will be returned.
-    /// If removing the item fails, which may happen mostly in cases where this action triggers
-    /// a DB dump (which is decided according to the dump policy), an
-    /// `anyhow::Error` is returned. Otherwise the item will be removed and `Ok(true)` will be returned.
+    /// The keys removed are the ones [scan_prefix()](#method.scan_prefix) returns, so keys holding a set, a
+    /// hash or a sorted set are kept. The DB is dumped once for all of them according to the dump policy,
+    /// and isn't dumped at all if no key matches.
     ///
-    /// This method is very similar to [list_pop()](#method.list_pop), the only difference is that this
-    /// methods returns an indication and [list_pop()](#method.list_pop) returns the actual item that was removed.
-    pub fn list_rm_val<V: Serialize, N: AsRef<str>>(&mut self, name: N, value: &V) -> Result<bool> {
-        let name = name.as_ref();
-        match self.list_map.get_mut(name) {
-            Some(list) => {
-                let serialized_value = match self.ser.serialize_data(&value) {
-                    Ok(val) => val,
-                    Err(err) => {
-                        return Err(anyhow!(
-                            "Error serializing value: {}",
-                            err.to_string().replace('\n', "")
-                        ))
-                    }
-                };
-                match list.iter().position(|x| *x == serialized_value) {
-                    Some(pos) => {
-                        list.remove(pos);
-                        match self.dumpdb() {
-                            Ok(_) => Ok(true),
-                            Err(err) => {
-                                let same_list = self.list_map.get_mut(name).unwrap();
-                                same_list.insert(pos, serialized_value);
-                                Err(err)
-                            }
-                        }
-                    }
-                    None => Ok(false),
-                }
-            }
-            None => Ok(false),
-        }
+    /// This method returns `Ok(count)` with the number of keys that were removed. An `anyhow::Error` is
+    /// returned and no key is removed if the dump fails.
+    pub fn rem_prefix<P: AsRef<str>>(&mut self, prefix: P) -> Result<usize> {
+        self.rem_keys(prefix.as_ref().to_string(), |_| true)
     }
 
-    /// Return an iterator over the keys and values in the DB.
-    pub fn iter(&self) -> NoDbIter {
-        NoDbIter {
-            map_iter: self.map.iter(),
+    /// Remove every value and list whose key matches a glob pattern from the DB.
+    ///
+    /// The keys removed are the ones [keys_matching()](#method.keys_matching) returns. Otherwise this method
+    /// behaves like [rem_prefix()](#method.rem_prefix).
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use nodb::{NoDb, DumpPolicy, SerializationMethod};
+    ///
+    /// let mut db = NoDb::load("example.db", DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+    /// let removed = db.rem_matching("session_*").unwrap();
+    /// println!("{} sessions removed", removed);
+    /// ```
+    pub fn rem_matching<P: AsRef<str>>(&mut self, pattern: P) -> Result<usize> {
+        let pattern = pattern.as_ref();
+        self.rem_keys(glob::literal_prefix(pattern).to_string(), |key| {
+            glob::matches(pattern, key)
+        })
+    }
+
+    /// Finds the values and lists whose keys start with `prefix` and are accepted by `filter`.
+    fn scan<'a, F: Fn(&str) -> bool + 'a>(&'a self, prefix: String, filter: F) -> NoDbScanIter<'a> {
+        let now = ttl::now();
+        let keep = move |(key, _): &(&String, ScanData<'_>)| {
+            filter(key) && !is_expired(&self.expires, key, now)
+        };
+        let item = |(key, data): (&'a String, ScanData<'a>)| NoDbScanItem {
+            key,
+            data,
             ser: &self.ser,
+        };
+        let items: Box<dyn Iterator<Item = NoDbScanItem<'a>> + 'a> = match &self.key_index {
+            Some(index) => Box::new(
+                index
+                    .range((Bound::Included(prefix.clone()), Bound::Unbounded))
+                    .take_while(move |key| key.starts_with(&prefix))
+                    .filter_map(|key| {
+                        let data = match self.map.get(key) {
+                            Some(value) => ScanData::Value(value),
+                            None => ScanData::List(self.list_map.get(key)?),
+                        };
+                        Some((key, data))
+                    })
+                    .filter(keep)
+                    .map(item),
+            ),
+            None => {
+                let values = self
+                    .map
+                    .iter()
+                    .map(|(key, value)| (key, ScanData::Value(value)));
+                let lists = self
+                    .list_map
+                    .iter()
+                    .map(|(name, list)| (name, ScanData::List(list)));
+                let mut items = values
+                    .chain(lists)
+                    .filter(|(key, _)| key.starts_with(&prefix))
+                    .filter(keep)
+                    .collect::<Vec<_>>();
+                items.sort_unstable_by_key(|(key, _)| *key);
+                Box::new(items.into_iter().map(item))
+            }
+        };
+        NoDbScanIter { items }
+    }
+
+    /// Removes the values and lists that [scan()](#method.scan) finds, with a single dump.
+    fn rem_keys<F: Fn(&str) -> bool>(&mut self, prefix: String, filter: F) -> Result<usize> {
+        let ops = self
+            .scan(prefix, filter)
+            .map(|item| LogOp::Rem {
+                key: item.key.to_string(),
+            })
+            .collect::<Vec<_>>();
+        if ops.is_empty() {
+            return Ok(0);
         }
+        let count = ops.len();
+        self.commit(ops)?;
+        Ok(count)
     }
 
     /// Return an iterator over the items in certain list.
-    pub fn list_iter<N: AsRef<str>>(&self, name: N) -> NoDbListIter {
+    pub fn list_iter<N: AsRef<str>>(&self, name: N) -> NoDbListIter<'_> {
         let name = name.as_ref();
-        match self.list_map.get(name) {
+        match self.list(name) {
             Some(list) => NoDbListIter {
                 list_iter: list.iter(),
                 ser: &self.ser,
@@ -517,12 +2694,616 @@ impl NoDb {
             },
         }
     }
+
+    /// Returns the value of a key, unless it expired.
+    fn value(&self, key: &str) -> Option<&Vec<u8>> {
+        let value = self.map.get(key)?;
+        (!is_expired_now(&self.expires, key)).then_some(value)
+    }
+
+    /// Returns a list, unless it expired.
+    fn list(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
+        let list = self.list_map.get(name)?;
+        (!is_expired_now(&self.expires, name)).then_some(list)
+    }
+
+    /// Returns the members of a set, unless it expired.
+    fn members(&self, name: &str) -> Option<&HashSet<Vec<u8>>> {
+        let set = self.set_map.get(name)?;
+        (!is_expired_now(&self.expires, name)).then_some(set)
+    }
+
+    /// Returns the fields of a hash, unless it expired.
+    fn hash(&self, key: &str) -> Option<&HashMap<String, Vec<u8>>> {
+        let hash = self.hash_map.get(key)?;
+        (!is_expired_now(&self.expires, key)).then_some(hash)
+    }
+
+    /// Returns a sorted set, unless it expired.
+    fn zset(&self, key: &str) -> Option<&SortedSet> {
+        let zset = self.zset_map.get(key)?;
+        (!is_expired_now(&self.expires, key)).then_some(zset)
+    }
+
+    /// Describes what a key holds, unless it expired or doesn't exist.
+    fn held(&self, key: &str) -> Option<Held> {
+        if self.value(key).is_some() {
+            Some(Held::Value)
+        } else if self.list(key).is_some() {
+            Some(Held::List)
+        } else if self.members(key).is_some() {
+            Some(Held::Set)
+        } else if self.hash(key).is_some() {
+            Some(Held::Hash)
+        } else if self.zset(key).is_some() {
+            Some(Held::ZSet)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the keys of every value, list, set, hash and sorted set, including the expired ones.
+    fn keys(&self) -> impl Iterator<Item = &String> {
+        self.map
+            .keys()
+            .chain(self.list_map.keys())
+            .chain(self.set_map.keys())
+            .chain(self.hash_map.keys())
+            .chain(self.zset_map.keys())
+    }
+
+    /// Borrows the maps of the DB, so that changes can be applied to them.
+    fn data_mut(&mut self) -> DbDataMut<'_> {
+        DbDataMut {
+            map: Arc::make_mut(&mut self.map),
+            list_map: Arc::make_mut(&mut self.list_map),
+            set_map: Arc::make_mut(&mut self.set_map),
+            hash_map: Arc::make_mut(&mut self.hash_map),
+            zset_map: Arc::make_mut(&mut self.zset_map),
+            expires: Arc::make_mut(&mut self.expires),
+            key_index: self.key_index.as_mut(),
+        }
+    }
+
+    /// Returns the changes that bring a key from any state to its current one.
+    fn key_ops(&self, key: &str) -> Vec<LogOp> {
+        let mut ops = if let Some(value) = self.map.get(key) {
+            vec![LogOp::Set {
+                key: key.to_string(),
+                value: value.clone(),
+            }]
+        } else if let Some(list) = self.list_map.get(key) {
+            vec![
+                LogOp::ListCreate {
+                    name: key.to_string(),
+                },
+                LogOp::ListExtend {
+                    name: key.to_string(),
+                    items: list.clone(),
+                },
+            ]
+        } else if let Some(set) = self.set_map.get(key) {
+            vec![
+                LogOp::Rem {
+                    key: key.to_string(),
+                },
+                LogOp::SetAdd {
+                    name: key.to_string(),
+                    members: set.iter().cloned().collect(),
+                },
+            ]
+        } else if let Some(hash) = self.hash_map.get(key) {
+            vec![
+                LogOp::Rem {
+                    key: key.to_string(),
+                },
+                LogOp::HashPut {
+                    name: key.to_string(),
+                    fields: hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect(),
+                },
+            ]
+        } else if let Some(zset) = self.zset_map.get(key) {
+            vec![
+                LogOp::Rem {
+                    key: key.to_string(),
+                },
+                LogOp::ZSetAdd {
+                    name: key.to_string(),
+                    members: zset.iter().map(|(m, s)| (m.to_vec(), s)).collect(),
+                },
+            ]
+        } else {
+            vec![LogOp::Rem {
+                key: key.to_string(),
+            }]
+        };
+        if let Some(at) = self.expires.get(key) {
+            ops.push(LogOp::Expire {
+                key: key.to_string(),
+                at: Some(*at),
+            });
+        }
+        ops
+    }
 }
 
 impl Drop for NoDb {
     fn drop(&mut self) {
-        if !matches!(self.policy, DumpPolicy::Never | DumpPolicy::OnCall) {
-            let _ = self.dump();
+        if self.closed {
+            return;
+        }
+        let result = match self.policy {
+            DumpPolicy::Never => Ok(()),
+            // Changes appended to the write-ahead log are replayed when the DB is loaded.
+            DumpPolicy::OnCall if self.dirty && !self.journal => {
+                Err(anyhow!("Changes weren't dumped before the DB was dropped"))
+            }
+            DumpPolicy::OnCall => Ok(()),
+            _ => self.dump_if_dirty(),
+        };
+        if let Err(err) = result {
+            match self.drop_policy {
+                DropPolicy::Warn => eprintln!("nodb: {}: {:#}", self.path.display(), err),
+                DropPolicy::Panic if !thread::panicking() => {
+                    panic!("{}: {:#}", self.path.display(), err)
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Borrows the bounds of a range of keys as string slices.
+fn str_bounds<'a, K: AsRef<str> + 'a, R: RangeBounds<K>>(
+    range: &'a R,
+) -> (Bound<&'a str>, Bound<&'a str>) {
+    (
+        range.start_bound().map(|key| key.as_ref()),
+        range.end_bound().map(|key| key.as_ref()),
+    )
+}
+
+/// Returns `true` if a range of keys can't hold any key, e.g. because it starts after its end.
+fn is_empty_range((start, end): (Bound<&str>, Bound<&str>)) -> bool {
+    match (start, end) {
+        (Bound::Excluded(start), Bound::Excluded(end)) => start >= end,
+        (
+            Bound::Included(start) | Bound::Excluded(start),
+            Bound::Included(end) | Bound::Excluded(end),
+        ) => start > end,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{create_dir, read, remove_file, write},
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{
+        testing::TempDir, DropPolicy, DumpPolicy, NoDb, NoDbIter, NoDbScanIter, SerializationMethod,
+    };
+
+    #[test]
+    fn counters_only_read_numbers() {
+        for method in [SerializationMethod::Bin, SerializationMethod::Bit] {
+            let dir = TempDir::new("nodb-counters");
+            let mut db = NoDb::new(dir.path("test.db"), DumpPolicy::Auto, method).unwrap();
+            db.set("text", "a string long enough to start like an i64")
+                .unwrap();
+            db.hset("hash", "text", "another string long enough")
+                .unwrap();
+            db.set("num", 5i64).unwrap();
+
+            assert!(db.incr("text").is_err());
+            assert!(db.hincr_by("hash", "text", 1).is_err());
+            assert_eq!(
+                db.get::<_, String>("text").as_deref(),
+                Some("a string long enough to start like an i64")
+            );
+            assert_eq!(db.incr_by("num", 2).unwrap(), 7);
+            assert_eq!(db.hincr_by("hash", "count", 3).unwrap(), 3);
+        }
+    }
+
+    #[test]
+    fn float_counters_need_a_self_describing_method() {
+        for method in [SerializationMethod::Bin, SerializationMethod::Bit] {
+            let dir = TempDir::new("nodb-float-counters");
+            let mut db = NoDb::new(dir.path("test.db"), DumpPolicy::Auto, method).unwrap();
+            assert_eq!(db.incr("int").unwrap(), 1);
+            // An integer would be read as a tiny floating point number.
+            assert!(db.incr_by_float("int", 1.0).is_err());
+            assert!(db.incr_by_float("float", 1.0).is_err());
+            assert!(!db.exists("float"));
+            assert_eq!(db.incr("int").unwrap(), 2);
+        }
+
+        let dir = TempDir::new("nodb-float-counters");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Cbor,
+        )
+        .unwrap();
+        assert_eq!(db.incr("int").unwrap(), 1);
+        assert_eq!(db.incr_by_float("float", 1.5).unwrap(), 1.5);
+        assert_eq!(db.incr_by_float("int", 1.0).unwrap(), 2.0);
+        // Integer counters don't read floating point numbers.
+        assert!(db.incr("float").is_err());
+        assert_eq!(db.get::<_, f64>("float"), Some(1.5));
+    }
+
+    #[test]
+    fn type_errors_name_what_the_key_holds() {
+        let dir = TempDir::new("nodb-held");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("value", 1).unwrap();
+        db.list_create("list").unwrap();
+        db.set_add("set", &1).unwrap();
+        db.hset("hash", "field", 1).unwrap();
+        db.zadd("zset", &1, 1.0).unwrap();
+        fn error<T>(res: anyhow::Result<T>) -> String {
+            res.map(|_| ()).unwrap_err().to_string()
+        }
+
+        assert_eq!(error(db.incr("list")), "list holds a list, not a number");
+        assert_eq!(
+            error(db.incr_by_float("list", 1.0)),
+            "list holds a list, not a number"
+        );
+        assert_eq!(error(db.incr("set")), "set holds a set, not a number");
+        assert_eq!(
+            error(db.set_add("list", &1)),
+            "list holds a list, not a set"
+        );
+        assert_eq!(
+            error(db.set_add("hash", &1)),
+            "hash holds a hash, not a set"
+        );
+        assert_eq!(
+            error(db.set_add("zset", &1)),
+            "zset holds a sorted set, not a set"
+        );
+        assert_eq!(
+            error(db.hset("set", "field", 1)),
+            "set holds a set, not a hash"
+        );
+        assert_eq!(
+            error(db.hincr_by("value", "field", 1)),
+            "value holds a value, not a hash"
+        );
+        assert_eq!(
+            error(db.zadd("hash", &1, 1.0)),
+            "hash holds a hash, not a sorted set"
+        );
+        assert_eq!(db.incr("value").unwrap(), 2);
+        assert!(db.set_add("set", &2).unwrap());
+        assert_eq!(db.hincr_by("hash", "field", 1).unwrap(), 2);
+        assert!(!db.zadd("zset", &1, 2.0).unwrap());
+    }
+
+    #[test]
+    fn ordered_keys_follow_changes() {
+        let dir = TempDir::new("nodb-ordered-keys");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        )
+        .unwrap()
+        .with_ordered_keys();
+        for key in ["user_3", "user_1", "session_1", "user_2", "user_4"] {
+            db.set(key, 1).unwrap();
+        }
+        db.rem("user_2").unwrap();
+        db.list_create("user_4").unwrap();
+        let keys = |iter: NoDbIter<'_>| iter.map(|kv| kv.get_key().to_string()).collect::<Vec<_>>();
+
+        assert_eq!(keys(db.iter()), ["session_1", "user_1", "user_3"]);
+        assert_eq!(keys(db.range("user_1".."user_9")), ["user_1", "user_3"]);
+        assert_eq!(keys(db.range_rev("a"..="user_1")), ["user_1", "session_1"]);
+        let snapshot = db.snapshot();
+        assert_eq!(keys(snapshot.iter()), keys(db.iter()));
+        assert_eq!(snapshot.get_all(), db.get_all());
+        assert_eq!(db.get_all(), ["session_1", "user_1", "user_3", "user_4"]);
+
+        Arc::make_mut(&mut db.map).insert("user_0".to_string(), b"1".to_vec());
+        db.mark_dirty();
+        assert_eq!(keys(db.range("user_0".."user_2")), ["user_0", "user_1"]);
+    }
+
+    #[test]
+    fn scans_and_bulk_removals_cover_values_and_lists() {
+        for ordered in [false, true] {
+            let dir = TempDir::new("nodb-scans");
+            let mut db = NoDb::new(
+                dir.path("test.db"),
+                DumpPolicy::OnCall,
+                SerializationMethod::Json,
+            )
+            .unwrap();
+            if ordered {
+                db = db.with_ordered_keys();
+            }
+            db.set("user_2", 2).unwrap();
+            db.set("user_10", 10).unwrap();
+            db.list_create("user_1").unwrap();
+            db.list_add("user_1", &1).unwrap();
+            db.set("users", 0).unwrap();
+            db.set("session_1", 1).unwrap();
+            db.set_add("user_tags", &"a").unwrap();
+            db.hset("user_3", "name", "alice").unwrap();
+            db.zadd("user_scores", &"alice", 1.0).unwrap();
+            let keys = |iter: NoDbScanIter<'_>| {
+                iter.map(|item| item.get_key().to_string())
+                    .collect::<Vec<_>>()
+            };
+
+            assert_eq!(
+                keys(db.scan_prefix("user_")),
+                ["user_1", "user_10", "user_2"]
+            );
+            assert_eq!(keys(db.keys_matching("user_?")), ["user_1", "user_2"]);
+            assert_eq!(keys(db.keys_matching("*s")), ["users"]);
+            let list = db.scan_prefix("user_1").next().unwrap();
+            assert!(list.is_list());
+            assert_eq!(list.list_iter().count(), 1);
+
+            db.dump().unwrap();
+            assert_eq!(db.rem_matching("nothing_*").unwrap(), 0);
+            assert!(!db.is_dirty());
+
+            assert_eq!(db.rem_prefix("user_").unwrap(), 3);
+            assert!(keys(db.scan_prefix("user_")).is_empty());
+            assert!(db.set_contains("user_tags", &"a"));
+            assert_eq!(
+                db.hget::<String, _, _>("user_3", "name").as_deref(),
+                Some("alice")
+            );
+            assert_eq!(db.zlen("user_scores"), 1);
+            assert!(db.exists("users"));
+        }
+    }
+
+    #[test]
+    fn restore_from_only_accepts_intact_backups_of_the_same_method() {
+        let dir = TempDir::new("nodb-restore");
+        let path = dir.path("test.db");
+        let mut other = NoDb::new(
+            dir.path("other.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Cbor,
+        )
+        .unwrap();
+        other.set("a", 1).unwrap();
+        other.backup_to(dir.path("cbor.bak")).unwrap();
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("a", 1).unwrap();
+        db.list_create("list").unwrap();
+        db.backup_to(dir.path("json.bak")).unwrap();
+        let mut corrupted = read(dir.path("json.bak")).unwrap();
+        let pos = corrupted.iter().rposition(|byte| *byte == b':').unwrap() + 1;
+        corrupted[pos] = if corrupted[pos] == b'A' { b'B' } else { b'A' };
+        write(dir.path("corrupted.bak"), corrupted).unwrap();
+        db.set("a", 2).unwrap();
+        db.rem("list").unwrap();
+        db.set("b", 3).unwrap();
+
+        for backup in ["cbor.bak", "corrupted.bak", "missing.bak"] {
+            assert!(db.restore_from(dir.path(backup)).is_err(), "{}", backup);
+            assert_eq!(db.get::<_, i32>("a"), Some(2));
+            assert!(!db.list_exists("list"));
+        }
+
+        db.restore_from(dir.path("json.bak")).unwrap();
+        assert_eq!(db.get::<_, i32>("a"), Some(1));
+        assert!(db.list_exists("list"));
+        assert!(!db.exists("b"));
+        drop(db);
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get_all().len(), 2);
+        assert_eq!(db.get::<_, i32>("a"), Some(1));
+    }
+
+    #[test]
+    fn close_reports_dump_errors() {
+        let dir = TempDir::new("nodb-close");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        db.set("key", 1).unwrap();
+        db.close().unwrap();
+        let mut db = NoDb::load(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+
+        db.set("key", 2).unwrap();
+        // The DB file can't be written once it is a directory.
+        remove_file(&path).unwrap();
+        create_dir(&path).unwrap();
+        assert!(db.close().is_err());
+    }
+
+    #[test]
+    fn dropped_changes_are_handled_by_the_drop_policy() {
+        let dir = TempDir::new("nodb-drop");
+        let path = dir.path("test.db");
+        let open = |drop_policy| {
+            let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+            db.drop_policy = drop_policy;
+            db
+        };
+
+        let mut db = open(DropPolicy::Ignore);
+        db.set("key", 1).unwrap();
+        drop(db);
+        let mut db = open(DropPolicy::Panic);
+        db.set("key", 1).unwrap();
+        assert!(catch_unwind(AssertUnwindSafe(|| drop(db))).is_err());
+        let mut db = open(DropPolicy::Panic);
+        db.set("key", 1).unwrap();
+        db.dump().unwrap();
+        drop(db);
+
+        // Other dump policies dump the changes on drop.
+        let policy = DumpPolicy::Debounced(Duration::from_secs(60));
+        let mut db = NoDb::new(&path, policy, SerializationMethod::Json).unwrap();
+        db.set("key", 2).unwrap();
+        drop(db);
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(2));
+    }
+
+    #[test]
+    fn conditional_writes_check_the_current_state() {
+        let dir = TempDir::new("nodb-cas");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+
+        assert!(db.set_if_absent("key", 1).unwrap());
+        assert!(!db.set_if_absent("key", 2).unwrap());
+        db.list_create("list").unwrap();
+        assert!(!db.set_if_absent("list", 2).unwrap());
+
+        assert!(!db.set_if_eq("key", &2, &3).unwrap());
+        assert!(!db.set_if_eq("missing", &1, &3).unwrap());
+        assert!(db.set_if_eq("key", &1, &3).unwrap());
+        assert_eq!(db.get::<_, i32>("key"), Some(3));
+        // A value of another type is never equal to the expected one.
+        assert!(!db.rem_if_eq("key", &"3".to_string()).unwrap());
+        assert!(db.rem_if_eq("key", &3).unwrap());
+        assert!(!db.exists("key"));
+    }
+
+    #[test]
+    fn versions_change_with_every_write() {
+        let dir = TempDir::new("nodb-versions");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+
+        assert_eq!(db.version("key"), 0);
+        db.set("key", 1).unwrap();
+        let first = db.version("key");
+        assert!(first > 0);
+        db.set("other", 1).unwrap();
+        assert_eq!(db.version("key"), first);
+
+        assert!(db.set_if_version("key", first, 2).unwrap());
+        let second = db.version("key");
+        assert!(second > first);
+        assert!(!db.set_if_version("key", first, 3).unwrap());
+        assert!(!db.rem_if_version("key", first).unwrap());
+        assert_eq!(db.get::<_, i32>("key"), Some(2));
+
+        db.list_create("list").unwrap();
+        let list = db.version("list");
+        db.list_add("list", &1).unwrap();
+        assert!(db.version("list") > list);
+
+        assert!(db.rem_if_version("key", second).unwrap());
+        assert!(db.version("key") > second);
+        assert!(!db.exists("key"));
+    }
+
+    #[test]
+    fn sets_hashes_and_sorted_sets_are_queried_and_loaded() {
+        let dir = TempDir::new("nodb-collections");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        let sorted = |mut members: Vec<i32>| {
+            members.sort();
+            members
+        };
+
+        for member in [1, 2, 3] {
+            assert!(db.set_add("a", &member).unwrap());
+        }
+        assert!(!db.set_add("a", &1).unwrap());
+        for member in [3, 4] {
+            db.set_add("b", &member).unwrap();
+        }
+        assert!(db.set_rem("b", &4).unwrap());
+        assert!(!db.set_rem("b", &4).unwrap());
+        assert_eq!(sorted(db.set_union(&["a", "b", "missing"])), vec![1, 2, 3]);
+        assert_eq!(db.set_intersection::<i32, _>(&["a", "b"]), vec![3]);
+        assert!(db.set_intersection::<i32, _>(&["a", "missing"]).is_empty());
+        assert_eq!(
+            sorted(db.set_difference(&["a", "b", "missing"])),
+            vec![1, 2]
+        );
+        db.set("value", 1).unwrap();
+        assert!(db.set_add("value", &1).is_err());
+
+        db.hset("hash", "x", 1).unwrap();
+        db.hset("hash", "y", 2).unwrap();
+        db.hset("hash", "x", 3).unwrap();
+        assert!(db.hdel("hash", "y").unwrap());
+        assert!(!db.hdel("hash", "y").unwrap());
+        assert_eq!(db.hkeys("hash"), vec!["x".to_string()]);
+        assert_eq!(db.hgetall::<i32, _>("hash").get("x"), Some(&3));
+
+        for (member, score) in [("c", 3.0), ("a", 1.0), ("b", 1.0), ("d", -0.0)] {
+            assert!(db.zadd("zset", &member, score).unwrap());
         }
+        assert!(!db.zadd("zset", &"d", 0.0).unwrap());
+        assert_eq!(db.zincr_by("zset", &"c", 2.0).unwrap(), 5.0);
+        assert_eq!(db.zrank("zset", &"a"), Some(1));
+        assert_eq!(db.zrank("zset", &"missing"), None);
+        // Members with the same score are ordered by member.
+        assert_eq!(
+            db.zrange_by_rank::<String, _, _>("zset", 1..3),
+            vec![("a".to_string(), 1.0), ("b".to_string(), 1.0)]
+        );
+        assert_eq!(
+            db.zrange_by_score::<String, _, _>("zset", 0.0..=1.0),
+            vec![
+                ("d".to_string(), 0.0),
+                ("a".to_string(), 1.0),
+                ("b".to_string(), 1.0)
+            ]
+        );
+        db.close().unwrap();
+
+        let mut db = NoDb::load(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        assert_eq!(sorted(db.set_members("a")), vec![1, 2, 3]);
+        assert_eq!(db.hget::<i32, _, _>("hash", "x"), Some(3));
+        assert_eq!(db.set_len("b"), 1);
+        // Collections are removed along with their last member.
+        assert!(db.set_rem("b", &3).unwrap());
+        assert!(!db.exists("b"));
+        assert!(db.hdel("hash", "x").unwrap());
+        assert!(!db.exists("hash"));
+        assert_eq!(db.zlen("zset"), 4);
+        assert_eq!(
+            db.zpop_min::<String, _>("zset").unwrap(),
+            Some(("d".to_string(), 0.0))
+        );
+        assert_eq!(
+            db.zpop_max::<String, _>("zset").unwrap(),
+            Some(("c".to_string(), 5.0))
+        );
+        assert!(db.zrem("zset", &"a").unwrap());
+        assert!(!db.zrem("zset", &"a").unwrap());
+        assert_eq!(
+            db.zpop_min::<String, _>("zset").unwrap(),
+            Some(("b".to_string(), 1.0))
+        );
+        assert!(!db.exists("zset"));
     }
 }
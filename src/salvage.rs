@@ -0,0 +1,229 @@
+//! # Salvage
+//!
+//! Reading the entries of a NoDb file, and recovering what can be recovered from a damaged one.
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    data::DbData,
+    format::{EntryKind, Payload},
+    ser::{SerializeMethod, Serializer},
+};
+
+/// A report of the data lost while loading a damaged DB with
+/// [NoDb::load_salvage()](struct.NoDb.html#method.load_salvage).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SalvageReport {
+    /// The number of values, lists, sets, hashes and sorted sets that were recovered.
+    pub recovered: usize,
+    /// The keys of the values, lists, sets, hashes and sorted sets whose data was corrupted and that were
+    /// left out.
+    pub corrupted_keys: Vec<String>,
+    /// The number of entries of the file that were too damaged to even tell their keys.
+    pub unreadable_entries: usize,
+    /// The number of entries missing from the end of a truncated file.
+    pub missing_entries: usize,
+    /// The number of records of the write-ahead log or the journal that were corrupted and skipped.
+    pub corrupted_records: usize,
+}
+
+impl SalvageReport {
+    /// Returns `true` if nothing was lost.
+    pub fn is_clean(&self) -> bool {
+        self.corrupted_keys.is_empty()
+            && self.unreadable_entries == 0
+            && self.missing_entries == 0
+            && self.corrupted_records == 0
+    }
+}
+
+/// Deserializes the entries of a file into the data of a DB, along with the time their keys expire.
+///
+/// With `salvage` unset, the first corrupted entry is reported as an `anyhow::Error`.
+/// Otherwise every intact entry is recovered and the others are listed in the report.
+pub(crate) fn read_entries(
+    payload: Payload,
+    ser: &Serializer,
+    salvage: bool,
+) -> Result<(DbData, SalvageReport)> {
+    let mut data = DbData::default();
+    let mut report = SalvageReport {
+        missing_entries: payload.missing,
+        ..Default::default()
+    };
+
+    for (index, entry) in payload.entries.into_iter().enumerate() {
+        let kind = match entry.kind {
+            Some(kind) => kind,
+            None if !salvage => {
+                return Err(anyhow!(
+                    "Entry {} holds an unknown kind of data, or is corrupted",
+                    index
+                ))
+            }
+            None => {
+                report.unreadable_entries += 1;
+                continue;
+            }
+        };
+        let entry_db = entry
+            .data
+            .map(|entry_data| read_entry(ser, kind, &entry_data));
+        match entry_db {
+            Some(Ok(entry_db)) if entry.intact => {
+                report.recovered += entry_db.keys().count();
+                if let Some(at) = entry.expires {
+                    for key in entry_db.keys() {
+                        data.expires.insert(key.to_string(), at);
+                    }
+                }
+                data.extend(entry_db);
+            }
+            Some(Ok(entry_db)) => {
+                let keys = entry_db.keys().cloned().collect::<Vec<_>>();
+                if !salvage {
+                    return Err(anyhow!(
+                        "Entry {} is corrupted (keys: {})",
+                        index,
+                        keys.join(", ")
+                    ));
+                }
+                report.corrupted_keys.extend(keys);
+            }
+            Some(Err(err)) if entry.intact && !salvage => return Err(err),
+            _ if !salvage => return Err(anyhow!("Entry {} is corrupted", index)),
+            _ => report.unreadable_entries += 1,
+        }
+    }
+
+    if !salvage {
+        if report.missing_entries != 0 {
+            return Err(anyhow!(
+                "File is truncated, {} entries are missing",
+                report.missing_entries
+            ));
+        }
+        if !payload.intact {
+            return Err(anyhow!("File checksum mismatch"));
+        }
+    }
+    Ok((data, report))
+}
+
+/// Deserializes the data of a single entry of a file.
+fn read_entry(ser: &Serializer, kind: EntryKind, entry_data: &[u8]) -> Result<DbData> {
+    let mut data = DbData::default();
+    match kind {
+        EntryKind::Value => {
+            let (key, value) = ser
+                .deserialize_value(entry_data)
+                .ok_or_else(|| anyhow!("Failed to deserialize value"))?;
+            data.map.insert(key, value);
+        }
+        EntryKind::List => {
+            let (name, list) = ser
+                .deserialize_list(entry_data)
+                .ok_or_else(|| anyhow!("Failed to deserialize list"))?;
+            data.list_map.insert(name, list);
+        }
+        EntryKind::Set => {
+            let (name, set) = ser
+                .deserialize_set(entry_data)
+                .ok_or_else(|| anyhow!("Failed to deserialize set"))?;
+            data.set_map.insert(name, set);
+        }
+        EntryKind::Hash => {
+            let (name, hash) = ser
+                .deserialize_hash(entry_data)
+                .ok_or_else(|| anyhow!("Failed to deserialize hash"))?;
+            data.hash_map.insert(name, hash);
+        }
+        EntryKind::ZSet => {
+            let (name, zset) = ser
+                .deserialize_zset(entry_data)
+                .ok_or_else(|| anyhow!("Failed to deserialize sorted set"))?;
+            data.zset_map.insert(name, zset);
+        }
+        EntryKind::Legacy => {
+            let (map, list_map) = ser.deserialized_db(entry_data)?;
+            data.map = map;
+            data.list_map = list_map;
+        }
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read, write},
+        path::Path,
+    };
+
+    use super::SalvageReport;
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    /// Creates a DB holding the values `a`, `b` and `c` and a list, and returns the lines of its file.
+    fn create(path: &Path) -> Vec<String> {
+        let mut db = NoDb::new(path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        for (key, value) in [("a", 1), ("b", 2), ("c", 3)] {
+            db.set(key, value).unwrap();
+        }
+        db.list_create("list").unwrap();
+        db.list_add("list", &4).unwrap();
+        db.close().unwrap();
+        let content = String::from_utf8(read(path).unwrap()).unwrap();
+        content.lines().map(String::from).collect()
+    }
+
+    fn salvage(path: &Path) -> (NoDb, SalvageReport) {
+        NoDb::load_salvage(path, DumpPolicy::Never, SerializationMethod::Json).unwrap()
+    }
+
+    #[test]
+    fn corrupted_entries_are_left_out() {
+        let dir = TempDir::new("salvage-corrupted");
+        let path = dir.path("test.db");
+        let mut lines = create(&path);
+        // The first line holds the header. One entry gets a wrong checksum, another garbled data.
+        lines[1].replace_range(..16, "0123456789abcdef");
+        let len = lines[2].len();
+        lines[2].truncate(len - 4);
+        write(&path, lines.join("\n") + "\n").unwrap();
+
+        assert!(NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).is_err());
+        let (db, report) = salvage(&path);
+        assert_eq!(report.recovered, 2);
+        assert_eq!(report.corrupted_keys.len(), 1);
+        assert_eq!(report.unreadable_entries, 1);
+        assert!(!report.is_clean());
+        assert_eq!(db.total_keys(), 2);
+        assert!(!db.exists(&report.corrupted_keys[0]));
+    }
+
+    #[test]
+    fn truncated_files_are_salvaged() {
+        let dir = TempDir::new("salvage-truncated");
+        let path = dir.path("test.db");
+        let lines = create(&path);
+        write(&path, lines[..lines.len() - 1].join("\n") + "\n").unwrap();
+
+        assert!(NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).is_err());
+        let (db, report) = salvage(&path);
+        assert_eq!(report.missing_entries, 1);
+        assert_eq!(report.recovered, 3);
+        assert_eq!(db.total_keys(), 3);
+    }
+
+    #[test]
+    fn intact_files_are_clean() {
+        let dir = TempDir::new("salvage-intact");
+        let path = dir.path("test.db");
+        create(&path);
+
+        let (db, report) = salvage(&path);
+        assert!(report.is_clean());
+        assert_eq!(report.recovered, 4);
+        assert_eq!(db.list_get::<i32, _>("list", 0), Some(4));
+    }
+}
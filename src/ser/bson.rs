@@ -2,7 +2,7 @@ use super::{DbListMap, DbMap, SerializeMethod};
 
 use anyhow::{anyhow, Result};
 use serde::{de::DeserializeOwned, Serialize};
-use std::{collections::HashMap, str::from_utf8};
+use std::collections::HashMap;
 
 use bson::{from_slice, to_vec};
 
@@ -18,21 +18,6 @@ impl SerializeMethod for BsonSer {
     fn serialize_data<T: Serialize>(&self, data: &T) -> Result<Vec<u8>> {
         Ok(to_vec(data)?)
     }
-    fn serialize_db(&self, db_map: &DbMap, db_list_map: &DbListMap) -> Result<Vec<u8>> {
-        let mut map = HashMap::new();
-        for (k, v) in db_map.iter() {
-            map.insert(k.as_str(), from_utf8(v)?);
-        }
-        let mut list_map = HashMap::new();
-        for (k, v) in db_list_map.iter() {
-            let list = v
-                .iter()
-                .map(|x| from_utf8(x).unwrap_or(""))
-                .collect::<Vec<_>>();
-            list_map.insert(k.as_str(), list);
-        }
-        Ok(to_vec(&(map, list_map))?)
-    }
     fn deserialize_data<T: DeserializeOwned>(&self, data: &[u8]) -> Option<T> {
         from_slice(data).ok()
     }
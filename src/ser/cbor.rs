@@ -16,9 +16,6 @@ impl SerializeMethod for CborSer {
     fn serialize_data<T: Serialize>(&self, data: &T) -> Result<Vec<u8>> {
         Ok(to_vec(data)?)
     }
-    fn serialize_db(&self, db_map: &DbMap, db_list_map: &DbListMap) -> Result<Vec<u8>> {
-        self.serialize_data(&(db_map, db_list_map))
-    }
     fn deserialize_data<T: DeserializeOwned>(&self, data: &[u8]) -> Option<T> {
         from_slice(data).ok()
     }
@@ -6,11 +6,14 @@ use cbor::CborSer;
 use json::JsonSer;
 use pot::PotSer;
 use ron::RonSer;
-use serde::{de::DeserializeOwned, Serialize};
-use std::fmt::{Display, Formatter, Result as FmtResult};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{Display, Formatter, Result as FmtResult},
+};
 use toml::TomlSer;
 
-use crate::{DbListMap, DbMap};
+use crate::{zset::SortedSet, DbListMap, DbMap};
 
 mod bin;
 mod bit;
@@ -59,7 +62,6 @@ impl Display for SerializationMethod {
 
 pub trait SerializeMethod {
     fn serialize_data<T: Serialize>(&self, data: &T) -> Result<Vec<u8>>;
-    fn serialize_db(&self, db_map: &DbMap, db_list_map: &DbListMap) -> Result<Vec<u8>>;
     fn deserialize_data<T: DeserializeOwned>(&self, data: &[u8]) -> Option<T>;
     fn deserialized_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap)>;
 }
@@ -75,6 +77,142 @@ pub(super) enum Serializer {
     Pot(PotSer),
 }
 
+/// A value as stored in an entry of the DB file, along with its key. Entries are wrapped in a struct
+/// since some formats (e.g. TOML or BSON) can only serialize a table at the top level.
+#[derive(Serialize, Deserialize)]
+struct ValueEntry<K, V> {
+    key: K,
+    value: V,
+}
+
+/// A list as stored in an entry of the DB file.
+#[derive(Serialize, Deserialize)]
+struct ListEntry<N, L> {
+    name: N,
+    items: L,
+}
+
+/// A set as stored in an entry of the DB file.
+#[derive(Serialize, Deserialize)]
+struct SetEntry<N, M> {
+    name: N,
+    members: M,
+}
+
+/// A hash as stored in an entry of the DB file, each field holding its own serialized value.
+#[derive(Serialize, Deserialize)]
+struct HashEntry<N, F> {
+    name: N,
+    fields: F,
+}
+
+/// A sorted set as stored in an entry of the DB file, its members being listed along with their scores.
+#[derive(Serialize, Deserialize)]
+struct ZSetEntry<N, M> {
+    name: N,
+    members: M,
+}
+
+/// The members of a sorted set along with their scores, serialized as a sequence of pairs without
+/// being collected first.
+struct ZSetMembers<'a>(&'a SortedSet);
+
+impl Serialize for ZSetMembers<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.0.iter())
+    }
+}
+
+impl Serializer {
+    /// Serializes a value along with its key.
+    pub(crate) fn serialize_value(&self, key: &str, value: &[u8]) -> Result<Vec<u8>> {
+        self.serialize_data(&ValueEntry { key, value })
+    }
+
+    /// Deserializes a value serialized with [serialize_value()](#method.serialize_value).
+    pub(crate) fn deserialize_value(&self, data: &[u8]) -> Option<(String, Vec<u8>)> {
+        self.deserialize_data::<ValueEntry<String, Vec<u8>>>(data)
+            .map(|entry| (entry.key, entry.value))
+    }
+
+    /// Serializes a list along with its name.
+    pub(crate) fn serialize_list(&self, name: &str, list: &[Vec<u8>]) -> Result<Vec<u8>> {
+        self.serialize_data(&ListEntry { name, items: list })
+    }
+
+    /// Deserializes a list serialized with [serialize_list()](#method.serialize_list).
+    pub(crate) fn deserialize_list(&self, data: &[u8]) -> Option<(String, Vec<Vec<u8>>)> {
+        self.deserialize_data::<ListEntry<String, Vec<Vec<u8>>>>(data)
+            .map(|entry| (entry.name, entry.items))
+    }
+
+    /// Serializes a set along with its name.
+    pub(crate) fn serialize_set(&self, name: &str, set: &HashSet<Vec<u8>>) -> Result<Vec<u8>> {
+        self.serialize_data(&SetEntry { name, members: set })
+    }
+
+    /// Deserializes a set serialized with [serialize_set()](#method.serialize_set).
+    pub(crate) fn deserialize_set(&self, data: &[u8]) -> Option<(String, HashSet<Vec<u8>>)> {
+        self.deserialize_data::<SetEntry<String, HashSet<Vec<u8>>>>(data)
+            .map(|entry| (entry.name, entry.members))
+    }
+
+    /// Serializes a hash along with its name.
+    pub(crate) fn serialize_hash(
+        &self,
+        name: &str,
+        hash: &HashMap<String, Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        self.serialize_data(&HashEntry { name, fields: hash })
+    }
+
+    /// Deserializes a hash serialized with [serialize_hash()](#method.serialize_hash).
+    pub(crate) fn deserialize_hash(
+        &self,
+        data: &[u8],
+    ) -> Option<(String, HashMap<String, Vec<u8>>)> {
+        self.deserialize_data::<HashEntry<String, HashMap<String, Vec<u8>>>>(data)
+            .map(|entry| (entry.name, entry.fields))
+    }
+
+    /// Serializes a sorted set along with its name.
+    pub(crate) fn serialize_zset(&self, name: &str, zset: &SortedSet) -> Result<Vec<u8>> {
+        self.serialize_data(&ZSetEntry {
+            name,
+            members: ZSetMembers(zset),
+        })
+    }
+
+    /// Deserializes a sorted set serialized with [serialize_zset()](#method.serialize_zset).
+    pub(crate) fn deserialize_zset(&self, data: &[u8]) -> Option<(String, SortedSet)> {
+        let entry = self.deserialize_data::<ZSetEntry<String, Vec<(Vec<u8>, f64)>>>(data)?;
+        let mut zset = SortedSet::new();
+        for (member, score) in entry.members {
+            zset.insert(member, score);
+        }
+        Some((entry.name, zset))
+    }
+
+    /// Returns `false` for the methods that don't write the type of the data along with it, whose data
+    /// may be read as a type other than the one it was written as.
+    pub(crate) fn is_self_describing(&self) -> bool {
+        !matches!(self, Serializer::Bin(_) | Serializer::Bit(_))
+    }
+
+    pub(crate) fn method(&self) -> SerializationMethod {
+        match self {
+            Serializer::Json(_) => SerializationMethod::Json,
+            Serializer::Bin(_) => SerializationMethod::Bin,
+            Serializer::Cbor(_) => SerializationMethod::Cbor,
+            Serializer::Toml(_) => SerializationMethod::Toml,
+            Serializer::Bit(_) => SerializationMethod::Bit,
+            Serializer::Ron(_) => SerializationMethod::Ron,
+            Serializer::Bson(_) => SerializationMethod::Bson,
+            Serializer::Pot(_) => SerializationMethod::Pot,
+        }
+    }
+}
+
 impl From<SerializationMethod> for Serializer {
     fn from(value: SerializationMethod) -> Self {
         match value {
@@ -104,19 +242,6 @@ impl SerializeMethod for Serializer {
         }
     }
 
-    fn serialize_db(&self, db_map: &DbMap, db_list_map: &DbListMap) -> Result<Vec<u8>> {
-        match self {
-            Serializer::Json(json_ser) => json_ser.serialize_db(db_map, db_list_map),
-            Serializer::Bin(bin_ser) => bin_ser.serialize_db(db_map, db_list_map),
-            Serializer::Cbor(cbor_ser) => cbor_ser.serialize_db(db_map, db_list_map),
-            Serializer::Toml(toml_ser) => toml_ser.serialize_db(db_map, db_list_map),
-            Serializer::Bit(bit_ser) => bit_ser.serialize_db(db_map, db_list_map),
-            Serializer::Ron(ron_ser) => ron_ser.serialize_db(db_map, db_list_map),
-            Serializer::Bson(bson_ser) => bson_ser.serialize_db(db_map, db_list_map),
-            Serializer::Pot(pot_ser) => pot_ser.serialize_db(db_map, db_list_map),
-        }
-    }
-
     fn deserialize_data<T: DeserializeOwned>(&self, data: &[u8]) -> Option<T> {
         match self {
             Serializer::Json(json_ser) => json_ser.deserialize_data(data),
@@ -143,3 +268,130 @@ impl SerializeMethod for Serializer {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, read, write},
+        time::Duration,
+    };
+
+    use serde::{Deserialize, Serialize};
+
+    use super::SerializationMethod;
+    use crate::{crypto::B64, testing::TempDir, DumpPolicy, NoDb};
+
+    const METHODS: [SerializationMethod; 8] = [
+        SerializationMethod::Json,
+        SerializationMethod::Bin,
+        SerializationMethod::Cbor,
+        SerializationMethod::Toml,
+        SerializationMethod::Bit,
+        SerializationMethod::Ron,
+        SerializationMethod::Bson,
+        SerializationMethod::Pot,
+    ];
+
+    // A struct rather than a scalar, as TOML can only serialize a table at the top level.
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Value {
+        num: i64,
+        text: String,
+    }
+
+    fn value(num: i64) -> Value {
+        Value {
+            num,
+            text: "héllo".to_string(),
+        }
+    }
+
+    /// Stores one of every kind of data in the DB.
+    fn fill(db: &mut NoDb) {
+        db.set("value", value(1)).unwrap();
+        db.list_create("list").unwrap();
+        db.list_extend("list", &[value(2), value(3)]).unwrap();
+        db.list_create("empty").unwrap();
+        db.set_add("set", &value(4)).unwrap();
+        db.hset("hash", "field", value(5)).unwrap();
+        db.zadd("zset", &value(6), 1.5).unwrap();
+        db.set_with_ttl("ttl", value(7), Duration::from_secs(100))
+            .unwrap();
+    }
+
+    /// Checks that the DB holds what [fill()] stored.
+    fn check(db: &NoDb, method: SerializationMethod) {
+        assert_eq!(db.get::<_, Value>("value"), Some(value(1)), "{}", method);
+        assert_eq!(
+            db.list_get::<Value, _>("list", 1),
+            Some(value(3)),
+            "{}",
+            method
+        );
+        assert_eq!(db.list_len("empty"), 0, "{}", method);
+        assert!(db.list_exists("empty"), "{}", method);
+        assert!(db.set_contains("set", &value(4)), "{}", method);
+        assert_eq!(
+            db.hget::<Value, _, _>("hash", "field"),
+            Some(value(5)),
+            "{}",
+            method
+        );
+        assert_eq!(db.zscore("zset", &value(6)), Some(1.5), "{}", method);
+        assert!(db.ttl("ttl").is_some(), "{}", method);
+    }
+
+    #[test]
+    fn every_method_dumps_and_loads_every_kind_of_data() {
+        for method in METHODS {
+            let dir = TempDir::new("ser-round-trip");
+            let path = dir.path("test.db");
+            let mut db = NoDb::new(&path, DumpPolicy::OnCall, method).unwrap();
+            fill(&mut db);
+            db.dump().unwrap();
+            drop(db);
+
+            // The method is read from the header of the file, whatever is passed here.
+            let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+            check(&db, method);
+            assert_eq!(db.total_keys(), 7, "{}", method);
+        }
+    }
+
+    #[test]
+    fn every_method_logs_and_replays_every_kind_of_data() {
+        for method in METHODS {
+            let dir = TempDir::new("ser-replay");
+            let path = dir.path("test.db");
+            let mut db = NoDb::new(&path, DumpPolicy::Auto, method).unwrap();
+            // Large enough for the log to hold every change without being folded into the file.
+            let padding = Value {
+                num: 0,
+                text: "x".repeat(1000),
+            };
+            db.set("padding", padding).unwrap();
+            let snapshot = read(&path).unwrap();
+            fill(&mut db);
+            assert_eq!(read(&path).unwrap(), snapshot, "{}", method);
+
+            // Copies the DB and its log while it is still open, as they would be found after a crash.
+            let copy = dir.path("copy.db");
+            fs::copy(&path, &copy).unwrap();
+            fs::copy(dir.path("test.db.log"), dir.path("copy.db.log")).unwrap();
+            let db = NoDb::load(&copy, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+            check(&db, method);
+            assert_eq!(db.total_keys(), 8, "{}", method);
+        }
+    }
+
+    #[test]
+    fn legacy_files_are_loaded() {
+        let dir = TempDir::new("ser-legacy");
+        let path = dir.path("test.db");
+        write(&path, B64.encrypt(r#"[{"key":"1"},{"list":["2"]}]"#)).unwrap();
+
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+        assert_eq!(db.list_get::<i32, _>("list", 0), Some(2));
+    }
+}
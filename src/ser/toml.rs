@@ -19,27 +19,8 @@ impl SerializeMethod for TomlSer {
         let val = to_string(data)?;
         Ok(val.as_bytes().to_vec())
     }
-    fn serialize_db(&self, db_map: &DbMap, db_list_map: &DbListMap) -> Result<Vec<u8>> {
-        let mut map = HashMap::new();
-        for (k, v) in db_map.iter() {
-            map.insert(k.as_str(), from_utf8(v)?);
-        }
-        let mut list_map = HashMap::new();
-        for (k, v) in db_list_map.iter() {
-            let list = v
-                .iter()
-                .map(|x| from_utf8(x).unwrap_or(""))
-                .collect::<Vec<_>>();
-            list_map.insert(k.as_str(), list);
-        }
-        Ok(to_string(&(map, list_map))?.into_bytes())
-    }
     fn deserialize_data<T: DeserializeOwned>(&self, data: &[u8]) -> Option<T> {
-        from_str(match from_utf8(data).ok() {
-            Some(v) => v,
-            None => return None,
-        })
-        .ok()
+        from_str(from_utf8(data).ok()?).ok()
     }
     fn deserialized_db(&self, ser_db: &[u8]) -> Result<(DbMap, DbListMap)> {
         match from_str::<(HashMap<String, String>, HashMap<String, Vec<String>>)>(from_utf8(
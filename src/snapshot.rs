@@ -0,0 +1,361 @@
+//! # Snapshot
+//!
+//! An immutable copy of the data of a NoDb instance at a point in time, that can be read while the DB
+//! keeps being written to.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ops::RangeBounds,
+    sync::Arc,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    data::SharedData,
+    iter::{MapIter, NoDbIter, NoDbListIter},
+    ser::{SerializationMethod, SerializeMethod, Serializer},
+    ttl::{is_expired, is_expired_now, now},
+    zset::SortedSet,
+};
+
+/// A read-only view of a NoDb instance at a point in time. Returned in
+/// [NoDb::snapshot()](struct.NoDb.html#method.snapshot).
+///
+/// The snapshot owns its data, so it doesn't borrow the DB and isn't affected by the changes made to
+/// the DB after it was taken. Taking or cloning a snapshot is cheap, as the snapshot shares the maps of
+/// the DB: a map is only copied when the DB is changed while a snapshot still shares it.
+///
+/// Keys that expire after the snapshot was taken are hidden from it once they expire, as they are
+/// from the DB.
+///
+/// Snapshots of a DB that keeps its keys in order, see
+/// [NoDb::with_ordered_keys()](struct.NoDb.html#method.with_ordered_keys), yield their keys in order as
+/// well. As the index of the keys isn't shared, they are sorted when [iter()](#method.iter) or
+/// [get_all()](#method.get_all) is called.
+#[derive(Clone)]
+pub struct NoDbSnapshot {
+    data: SharedData,
+    ser: Arc<Serializer>,
+    ordered: bool,
+}
+
+impl NoDbSnapshot {
+    pub(crate) fn new(data: SharedData, ser_method: SerializationMethod, ordered: bool) -> Self {
+        NoDbSnapshot {
+            data,
+            ser: Arc::new(Serializer::from(ser_method)),
+            ordered,
+        }
+    }
+
+    /// Get a value of a key.
+    ///
+    /// If the key doesn't exist or if the type is wrong, `None` will be returned.
+    /// Otherwise `Some(V)` will be returned.
+    pub fn get<K: AsRef<str>, V: DeserializeOwned>(&self, key: K) -> Option<V> {
+        self.ser.deserialize_data(self.value(key.as_ref())?)
+    }
+
+    /// Check if a key exists.
+    pub fn exists<K: AsRef<str>>(&self, key: K) -> bool {
+        let key = key.as_ref();
+        self.value(key).is_some()
+            || self.list(key).is_some()
+            || self.members(key).is_some()
+            || self.hash(key).is_some()
+            || self.zset(key).is_some()
+    }
+
+    /// Get a vector of all the keys in the snapshot.
+    pub fn get_all(&self) -> Vec<String> {
+        let now = now();
+        let mut keys = self
+            .data
+            .keys()
+            .filter(|key| !is_expired(&self.data.expires, key, now))
+            .cloned()
+            .collect::<Vec<_>>();
+        if self.ordered {
+            keys.sort_unstable();
+        }
+        keys
+    }
+
+    /// Get the total number of keys in the snapshot.
+    pub fn total_keys(&self) -> usize {
+        let now = now();
+        self.data
+            .keys()
+            .filter(|key| !is_expired(&self.data.expires, key, now))
+            .count()
+    }
+
+    /// Check if a list exists.
+    pub fn list_exists<N: AsRef<str>>(&self, name: N) -> bool {
+        self.list(name.as_ref()).is_some()
+    }
+
+    /// Get an item of a certain list in a certain position.
+    ///
+    /// If the list is not found or the given position is out of bounds of the list `None` will be
+    /// returned. Otherwise `Some(V)` will be returned.
+    pub fn list_get<V: DeserializeOwned, N: AsRef<str>>(&self, name: N, pos: usize) -> Option<V> {
+        self.ser
+            .deserialize_data(self.list(name.as_ref())?.get(pos)?)
+    }
+
+    /// Get the length of a list.
+    ///
+    /// If the list is empty or if it doesn't exist the value of 0 is returned.
+    pub fn list_len<N: AsRef<str>>(&self, name: N) -> usize {
+        self.list(name.as_ref()).map_or(0, Vec::len)
+    }
+
+    /// Check if a set holds a member.
+    ///
+    /// This method returns `false` if the set isn't found or if the member can't be serialized.
+    pub fn set_contains<N: AsRef<str>, V: Serialize>(&self, name: N, value: &V) -> bool {
+        match (self.members(name.as_ref()), self.ser.serialize_data(value)) {
+            (Some(set), Ok(member)) => set.contains(&member),
+            _ => false,
+        }
+    }
+
+    /// Get the members of a set, in no particular order.
+    ///
+    /// Members that can't be deserialized to `V` are left out. If the set isn't found an empty vector is
+    /// returned.
+    pub fn set_members<V: DeserializeOwned, N: AsRef<str>>(&self, name: N) -> Vec<V> {
+        self.members(name.as_ref()).map_or_else(Vec::new, |set| {
+            set.iter()
+                .filter_map(|member| self.ser.deserialize_data(member))
+                .collect()
+        })
+    }
+
+    /// Get the number of members of a set.
+    ///
+    /// If the set doesn't exist the value of 0 is returned.
+    pub fn set_len<N: AsRef<str>>(&self, name: N) -> usize {
+        self.members(name.as_ref()).map_or(0, HashSet::len)
+    }
+
+    /// Get the value of a field of a hash.
+    ///
+    /// If the hash or the field doesn't exist or if the type is wrong, `None` will be returned.
+    /// Otherwise `Some(V)` will be returned.
+    pub fn hget<V: DeserializeOwned, K: AsRef<str>, F: AsRef<str>>(
+        &self,
+        key: K,
+        field: F,
+    ) -> Option<V> {
+        self.ser
+            .deserialize_data(self.hash(key.as_ref())?.get(field.as_ref())?)
+    }
+
+    /// Get the names of the fields of a hash, in no particular order.
+    ///
+    /// If the hash doesn't exist an empty vector is returned.
+    pub fn hkeys<K: AsRef<str>>(&self, key: K) -> Vec<String> {
+        self.hash(key.as_ref())
+            .map_or_else(Vec::new, |hash| hash.keys().cloned().collect())
+    }
+
+    /// Get the fields of a hash along with their values.
+    ///
+    /// Fields whose value can't be deserialized to `V` are left out. If the hash doesn't exist an empty
+    /// map is returned.
+    pub fn hgetall<V: DeserializeOwned, K: AsRef<str>>(&self, key: K) -> HashMap<String, V> {
+        self.hash(key.as_ref()).map_or_else(HashMap::new, |hash| {
+            hash.iter()
+                .filter_map(|(field, value)| {
+                    Some((field.clone(), self.ser.deserialize_data(value)?))
+                })
+                .collect()
+        })
+    }
+
+    /// Get the score of a member of a sorted set.
+    ///
+    /// This method returns `None` if the sorted set or the member isn't found, or if the member can't be
+    /// serialized.
+    pub fn zscore<K: AsRef<str>, V: Serialize>(&self, key: K, member: &V) -> Option<f64> {
+        let member = self.ser.serialize_data(member).ok()?;
+        self.zset(key.as_ref())?.score(&member)
+    }
+
+    /// Get the rank of a member of a sorted set, the member with the lowest score having the rank 0.
+    ///
+    /// Unlike the other sorted set operations, this takes a time proportional to the rank of the member.
+    ///
+    /// This method returns `None` if the sorted set or the member isn't found, or if the member can't be
+    /// serialized.
+    pub fn zrank<K: AsRef<str>, V: Serialize>(&self, key: K, member: &V) -> Option<usize> {
+        let member = self.ser.serialize_data(member).ok()?;
+        self.zset(key.as_ref())?.rank(&member)
+    }
+
+    /// Get the number of members of a sorted set.
+    ///
+    /// If the sorted set doesn't exist the value of 0 is returned.
+    pub fn zlen<K: AsRef<str>>(&self, key: K) -> usize {
+        self.zset(key.as_ref()).map_or(0, SortedSet::len)
+    }
+
+    /// Get the members of a sorted set whose rank is within a range, along with their scores.
+    ///
+    /// Members are returned from the lowest score to the highest. Members that can't be deserialized to
+    /// `V` are left out. If the sorted set isn't found an empty vector is returned.
+    ///
+    /// This takes a time proportional to the end of the range, as the members ranked before its start are
+    /// skipped one by one.
+    pub fn zrange_by_rank<V, K, R>(&self, key: K, range: R) -> Vec<(V, f64)>
+    where
+        V: DeserializeOwned,
+        K: AsRef<str>,
+        R: RangeBounds<usize>,
+    {
+        self.zset(key.as_ref()).map_or_else(Vec::new, |zset| {
+            zset.range_by_rank(range)
+                .filter_map(|(member, score)| Some((self.ser.deserialize_data(member)?, score)))
+                .collect()
+        })
+    }
+
+    /// Get the members of a sorted set whose score is within a range, along with their scores.
+    ///
+    /// Members are returned from the lowest score to the highest. Members that can't be deserialized to
+    /// `V` are left out. If the sorted set isn't found an empty vector is returned.
+    pub fn zrange_by_score<V, K, R>(&self, key: K, range: R) -> Vec<(V, f64)>
+    where
+        V: DeserializeOwned,
+        K: AsRef<str>,
+        R: RangeBounds<f64>,
+    {
+        self.zset(key.as_ref()).map_or_else(Vec::new, |zset| {
+            zset.range_by_score(range)
+                .filter_map(|(member, score)| Some((self.ser.deserialize_data(member)?, score)))
+                .collect()
+        })
+    }
+
+    /// Return an iterator over the keys and values in the snapshot.
+    pub fn iter(&self) -> NoDbIter<'_> {
+        let map_iter = if self.ordered {
+            let mut entries = self.data.map.iter().collect::<Vec<_>>();
+            entries.sort_unstable_by_key(|(key, _)| *key);
+            MapIter::Sorted(entries.into_iter())
+        } else {
+            MapIter::Unordered(self.data.map.iter())
+        };
+        NoDbIter {
+            map_iter,
+            ser: &self.ser,
+            expires: &self.data.expires,
+            now: now(),
+        }
+    }
+
+    /// Return an iterator over the items in certain list.
+    pub fn list_iter<N: AsRef<str>>(&self, name: N) -> NoDbListIter<'_> {
+        let list = self.list(name.as_ref()).map_or(&[][..], Vec::as_slice);
+        NoDbListIter {
+            list_iter: list.iter(),
+            ser: &self.ser,
+        }
+    }
+
+    fn value(&self, key: &str) -> Option<&Vec<u8>> {
+        let value = self.data.map.get(key)?;
+        (!is_expired_now(&self.data.expires, key)).then_some(value)
+    }
+
+    fn list(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
+        let list = self.data.list_map.get(name)?;
+        (!is_expired_now(&self.data.expires, name)).then_some(list)
+    }
+
+    fn members(&self, name: &str) -> Option<&HashSet<Vec<u8>>> {
+        let set = self.data.set_map.get(name)?;
+        (!is_expired_now(&self.data.expires, name)).then_some(set)
+    }
+
+    fn hash(&self, key: &str) -> Option<&HashMap<String, Vec<u8>>> {
+        let hash = self.data.hash_map.get(key)?;
+        (!is_expired_now(&self.data.expires, key)).then_some(hash)
+    }
+
+    fn zset(&self, key: &str) -> Option<&SortedSet> {
+        let zset = self.data.zset_map.get(key)?;
+        (!is_expired_now(&self.data.expires, key)).then_some(zset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{testing::TempDir, ttl::advance, DumpPolicy, NoDb, SerializationMethod};
+
+    #[test]
+    fn snapshots_are_unaffected_by_later_writes() {
+        let dir = TempDir::new("snapshot-isolation");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        db.list_create("list").unwrap();
+        db.list_add("list", &1).unwrap();
+        db.set_add("set", &1).unwrap();
+        db.hset("hash", "field", 1).unwrap();
+        db.zadd("zset", &"member", 1.0).unwrap();
+
+        let snapshot = db.snapshot();
+        db.set("a", 10).unwrap();
+        db.rem("b").unwrap();
+        db.set("c", 3).unwrap();
+        db.list_add("list", &2).unwrap();
+        db.set_add("set", &2).unwrap();
+        db.hset("hash", "field", 2).unwrap();
+        db.zincr_by("zset", &"member", 1.0).unwrap();
+
+        assert_eq!(snapshot.get::<_, i32>("a"), Some(1));
+        assert_eq!(snapshot.get::<_, i32>("b"), Some(2));
+        assert!(!snapshot.exists("c"));
+        assert_eq!(snapshot.total_keys(), 6);
+        assert_eq!(snapshot.list_len("list"), 1);
+        assert_eq!(snapshot.set_members::<i32, _>("set"), vec![1]);
+        assert_eq!(snapshot.hget::<i32, _, _>("hash", "field"), Some(1));
+        assert_eq!(snapshot.zscore("zset", &"member"), Some(1.0));
+
+        let later = db.snapshot();
+        assert_eq!(later.get::<_, i32>("a"), Some(10));
+        assert_eq!(later.list_len("list"), 2);
+        assert_eq!(later.zscore("zset", &"member"), Some(2.0));
+    }
+
+    #[test]
+    fn expired_keys_are_hidden_from_snapshots() {
+        let dir = TempDir::new("snapshot-expiry");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        db.expire("a", Duration::from_secs(60)).unwrap();
+
+        let snapshot = db.snapshot();
+        assert!(snapshot.exists("a"));
+        advance(Duration::from_secs(60));
+        assert!(!snapshot.exists("a"));
+        assert_eq!(snapshot.get_all(), vec!["b".to_string()]);
+    }
+}
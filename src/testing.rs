@@ -0,0 +1,37 @@
+//! # Testing
+//!
+//! Helpers shared by the unit tests.
+
+use std::{
+    fs::{create_dir_all, remove_dir_all},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary directory to hold the files of a test, removed once the test is done with it.
+pub(crate) struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates a directory, unique to the calling test, whose name starts with `name`.
+    pub(crate) fn new(name: &str) -> Self {
+        let id = NEXT_DIR.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("nodb-{}-{}-{}", name, process::id(), id));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    /// Returns the path of a file in the directory.
+    pub(crate) fn path(&self, file: &str) -> PathBuf {
+        self.0.join(file)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,140 @@
+//! # Temporary files
+//!
+//! Files are dumped to a temporary file next to them that is then renamed over them. The name of
+//! temporary files is unique to the process and the dump (`<db>.tmp.<secs>.<pid>.<counter>`), and
+//! the ones left behind by an interrupted dump are swept when the DB is loaded.
+
+use std::{
+    ffi::OsString,
+    fs::{read_dir, remove_file},
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Result;
+
+static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a unique path for a temporary file to be renamed to `path`.
+pub(crate) fn temp_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| dur.as_secs())
+        .unwrap_or(0);
+    let mut tmp = OsString::from(path.as_ref().as_os_str());
+    tmp.push(format!(
+        ".tmp.{}.{}.{}",
+        secs,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(tmp)
+}
+
+/// Lists the temporary files left next to `path`, most recently modified first.
+pub(crate) fn temp_files<P: AsRef<Path>>(path: P) -> Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let prefix = match path.file_name().and_then(|name| name.to_str()) {
+        Some(name) => format!("{}.tmp.", name),
+        None => return Ok(Vec::new()),
+    };
+    let dir = match path.parent() {
+        Some(parent) if parent != Path::new("") => parent,
+        _ => Path::new("."),
+    };
+
+    let mut files = Vec::new();
+    for entry in read_dir(dir)? {
+        let entry = entry?;
+        let is_temp = entry
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.starts_with(&prefix));
+        if is_temp {
+            let modified = entry.metadata()?.modified().unwrap_or(UNIX_EPOCH);
+            files.push((modified, entry.path()));
+        }
+    }
+    files.sort_by(|a, b| b.cmp(a));
+    Ok(files.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Removes the temporary files left next to `path`.
+pub(crate) fn sweep<P: AsRef<Path>>(path: P) -> Result<()> {
+    for file in temp_files(path)? {
+        remove_file(file)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{read, remove_file, write},
+        path::Path,
+    };
+
+    use super::{temp_files, temp_path};
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    fn create(path: &Path) {
+        let mut db = NoDb::new(path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("key", 1).unwrap();
+        db.close().unwrap();
+    }
+
+    #[test]
+    fn stale_temp_files_are_swept_on_load() {
+        let dir = TempDir::new("tmp-sweep");
+        let path = dir.path("test.db");
+        create(&path);
+        write(temp_path(&path), b"interrupted dump").unwrap();
+        write(dir.path("other.db.tmp.1.2.3"), b"another DB").unwrap();
+
+        let db = NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("key"), Some(1));
+        assert!(temp_files(&path).unwrap().is_empty());
+        assert!(dir.path("other.db.tmp.1.2.3").exists());
+    }
+
+    #[test]
+    fn an_interrupted_dump_is_recovered_from_its_temp_file() {
+        let dir = TempDir::new("tmp-recover");
+        let path = dir.path("test.db");
+        create(&path);
+        let content = read(&path).unwrap();
+
+        // The file is corrupted, or was removed before the temp file could be renamed over it.
+        for damaged in [Some(&content[..content.len() - 3]), None] {
+            match damaged {
+                Some(damaged) => write(&path, damaged).unwrap(),
+                None => remove_file(&path).unwrap(),
+            }
+            write(temp_path(&path), &content).unwrap();
+            // A failed load leaves the temp file for load_or_recover().
+            assert!(NoDb::load(&path, DumpPolicy::Auto, SerializationMethod::Json).is_err());
+            assert_eq!(temp_files(&path).unwrap().len(), 1);
+
+            let db =
+                NoDb::load_or_recover(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+            assert_eq!(db.get::<_, i32>("key"), Some(1));
+            assert_eq!(read(&path).unwrap(), content);
+            assert!(temp_files(&path).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn damaged_temp_files_are_not_recovered() {
+        let dir = TempDir::new("tmp-damaged");
+        let path = dir.path("test.db");
+        create(&path);
+        let content = read(&path).unwrap();
+        write(&path, b"garbage").unwrap();
+        write(temp_path(&path), &content[..content.len() - 3]).unwrap();
+
+        assert!(NoDb::load_or_recover(&path, DumpPolicy::Auto, SerializationMethod::Json).is_err());
+        assert_eq!(read(&path).unwrap(), b"garbage");
+    }
+}
@@ -0,0 +1,124 @@
+//! # Time to live
+//!
+//! Expiration of keys. Keys expire at a time stored in milliseconds since the Unix epoch, so that the
+//! time stays meaningful once dumped to the file and loaded again, possibly by another process.
+//!
+//! Expired keys are hidden from reads right away, and removed from the DB along with the next change
+//! made to it, by a [NoDbFlusher](struct.NoDbFlusher.html) or by
+//! [NoDb::purge_expired()](struct.NoDb.html#method.purge_expired).
+
+#[cfg(test)]
+use std::cell::Cell;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::DbExpiryMap;
+
+#[cfg(test)]
+thread_local! {
+    /// How far the tests running on this thread moved the clock forward, see [advance()].
+    static SKEW: Cell<u64> = const { Cell::new(0) };
+}
+
+/// Returns the current time in milliseconds since the Unix epoch.
+pub(crate) fn now() -> u64 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|dur| u64::try_from(dur.as_millis()).unwrap_or(u64::MAX))
+        .unwrap_or(0);
+    #[cfg(test)]
+    let now = now.saturating_add(SKEW.get());
+    now
+}
+
+/// Moves the clock of the current thread forward, so that tests can expire keys without waiting.
+#[cfg(test)]
+pub(crate) fn advance(by: Duration) {
+    SKEW.set(
+        SKEW.get()
+            .saturating_add(u64::try_from(by.as_millis()).unwrap_or(u64::MAX)),
+    );
+}
+
+/// Returns the time at which a key given the time to live `ttl` now expires.
+pub(crate) fn expires_at(ttl: Duration) -> u64 {
+    now().saturating_add(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX))
+}
+
+/// Returns `true` if the key is expired at the time `now`.
+pub(crate) fn is_expired(expires: &DbExpiryMap, key: &str, now: u64) -> bool {
+    expires.get(key).is_some_and(|at| *at <= now)
+}
+
+/// Returns `true` if the key is expired now. The clock is only read if the key expires at all, as most
+/// reads are of keys that don't.
+pub(crate) fn is_expired_now(expires: &DbExpiryMap, key: &str) -> bool {
+    !expires.is_empty() && expires.get(key).is_some_and(|at| *at <= now())
+}
+
+/// Returns the time at which the first key expires, or `u64::MAX` if no key expires.
+pub(crate) fn next_expiry(expires: &DbExpiryMap) -> u64 {
+    expires.values().min().copied().unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::advance;
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    #[test]
+    fn expiry_times_are_dumped_and_loaded() {
+        let dir = TempDir::new("ttl-load");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Bin).unwrap();
+        db.set_with_ttl("short", 1, Duration::from_secs(60))
+            .unwrap();
+        db.set_with_ttl("long", 2, Duration::from_secs(3600))
+            .unwrap();
+        db.list_create("list").unwrap();
+        db.expire("list", Duration::from_secs(3600)).unwrap();
+        db.set("kept", 3).unwrap();
+        db.set_with_ttl("persisted", 4, Duration::from_secs(3600))
+            .unwrap();
+        assert!(db.persist("persisted").unwrap());
+        db.close().unwrap();
+
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Bin).unwrap();
+        let long = db.ttl("long").unwrap();
+        assert!(long > Duration::from_secs(3500) && long <= Duration::from_secs(3600));
+        assert!(db.ttl("list").is_some());
+        assert_eq!(db.ttl("kept"), None);
+        assert_eq!(db.ttl("persisted"), None);
+        drop(db);
+
+        advance(Duration::from_secs(60));
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Bin).unwrap();
+        assert!(!db.exists("short"));
+        assert_eq!(db.get::<_, i32>("short"), None);
+        assert_eq!(db.ttl("short"), None);
+        assert_eq!(db.get::<_, i32>("long"), Some(2));
+        assert_eq!(db.get::<_, i32>("persisted"), Some(4));
+        assert_eq!(db.total_keys(), 4);
+        assert!(!db.get_all().contains(&"short".to_string()));
+    }
+
+    #[test]
+    fn expired_keys_are_purged_with_the_next_change() {
+        let dir = TempDir::new("ttl-purge");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json).unwrap();
+        db.set_with_ttl("a", 1, Duration::from_secs(60)).unwrap();
+        db.set_with_ttl("b", 2, Duration::from_secs(60)).unwrap();
+        advance(Duration::from_secs(60));
+        assert!(db.map.contains_key("a"));
+
+        db.set("c", 3).unwrap();
+        assert!(!db.map.contains_key("a") && !db.map.contains_key("b"));
+        assert_eq!(db.purge_expired().unwrap(), 0);
+        db.close().unwrap();
+
+        let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(db.map.len(), 1);
+    }
+}
@@ -0,0 +1,388 @@
+//! # Transaction
+//!
+//! A set of changes staged on top of a NoDb instance, that are applied to it together once the
+//! transaction is committed, or discarded if it is rolled back.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Result;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    ser::{SerializeMethod, Serializer},
+    ttl::{is_expired, now},
+    wal::LogOp,
+    zset::SortedSet,
+    DbExpiryMap, DbHashMap, DbListMap, DbMap, DbSetMap, DbZSetMap,
+};
+
+/// The staged state of a key changed in a transaction.
+enum Staged {
+    Value(Vec<u8>),
+    List(Vec<Vec<u8>>),
+    Removed,
+}
+
+/// A transaction on a NoDb instance. Passed to the closure given to
+/// [NoDb::transaction()](struct.NoDb.html#method.transaction).
+///
+/// Changes made in a transaction are staged: they are visible to the reads made through the
+/// transaction, but not to the DB until the transaction is committed.
+pub struct Transaction<'a> {
+    map: &'a DbMap,
+    list_map: &'a DbListMap,
+    set_map: &'a DbSetMap,
+    hash_map: &'a DbHashMap,
+    zset_map: &'a DbZSetMap,
+    expires: &'a DbExpiryMap,
+    now: u64,
+    ser: &'a Serializer,
+    staged: HashMap<String, Staged>,
+    pub(crate) ops: Vec<LogOp>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(
+        map: &'a DbMap,
+        list_map: &'a DbListMap,
+        set_map: &'a DbSetMap,
+        hash_map: &'a DbHashMap,
+        zset_map: &'a DbZSetMap,
+        expires: &'a DbExpiryMap,
+        ser: &'a Serializer,
+    ) -> Self {
+        Transaction {
+            map,
+            list_map,
+            set_map,
+            hash_map,
+            zset_map,
+            expires,
+            now: now(),
+            ser,
+            staged: HashMap::new(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Set a key-value pair.
+    ///
+    /// The key has to be a string but the value can be of any type that is serializable.
+    /// If a list, a set, a hash or a sorted set is set under this key, it is overridden by the value.
+    ///
+    /// This method returns an `anyhow::Error` if the value can't be serialized.
+    pub fn set<K: AsRef<str>, V: Serialize>(&mut self, key: K, value: V) -> Result<()> {
+        let key = key.as_ref();
+        let data = self.ser.serialize_data(&value)?;
+        self.staged
+            .insert(key.to_string(), Staged::Value(data.clone()));
+        self.ops.push(LogOp::Set {
+            key: key.to_string(),
+            value: data,
+        });
+        Ok(())
+    }
+
+    /// Get a value of a key, as seen by the transaction.
+    ///
+    /// If the key doesn't exist or if the type is wrong, `None` will be returned.
+    /// Otherwise `Some(V)` will be returned.
+    pub fn get<K: AsRef<str>, V: DeserializeOwned>(&self, key: K) -> Option<V> {
+        self.ser.deserialize_data(self.value(key.as_ref())?)
+    }
+
+    /// Check if a key exists, as seen by the transaction.
+    pub fn exists<K: AsRef<str>>(&self, key: K) -> bool {
+        let key = key.as_ref();
+        self.value(key).is_some()
+            || self.list(key).is_some()
+            || self.members(key).is_some()
+            || self.hash(key).is_some()
+            || self.zset(key).is_some()
+    }
+
+    /// Remove a key-value pair, a list, a set, a hash or a sorted set.
+    ///
+    /// This method returns `true` if the key was found and `false` otherwise.
+    pub fn rem<K: AsRef<str>>(&mut self, key: K) -> bool {
+        let key = key.as_ref();
+        if !self.exists(key) {
+            return false;
+        }
+        self.staged.insert(key.to_string(), Staged::Removed);
+        self.ops.push(LogOp::Rem {
+            key: key.to_string(),
+        });
+        true
+    }
+
+    /// Create a new empty list.
+    ///
+    /// If another list, value, set, hash or sorted set is already set under this key, it is overridden.
+    pub fn list_create<N: AsRef<str>>(&mut self, name: N) {
+        let name = name.as_ref();
+        self.staged
+            .insert(name.to_string(), Staged::List(Vec::new()));
+        self.ops.push(LogOp::ListCreate {
+            name: name.to_string(),
+        });
+    }
+
+    /// Check if a list exists, as seen by the transaction.
+    pub fn list_exists<N: AsRef<str>>(&self, name: N) -> bool {
+        self.list(name.as_ref()).is_some()
+    }
+
+    /// Add a single item to an existing list.
+    ///
+    /// This method returns `Ok(false)` if the list isn't found, and an `anyhow::Error` if the item
+    /// can't be serialized.
+    pub fn list_add<N: AsRef<str>, V: Serialize>(&mut self, name: N, value: &V) -> Result<bool> {
+        self.list_extend(name, [value])
+    }
+
+    /// Add multiple items to an existing list.
+    ///
+    /// This method returns `Ok(false)` if the list isn't found, and an `anyhow::Error` if one of the
+    /// items can't be serialized, in which case none of them are added.
+    pub fn list_extend<'b, N, V, I>(&mut self, name: N, seq: I) -> Result<bool>
+    where
+        N: AsRef<str>,
+        V: 'b + Serialize,
+        I: IntoIterator<Item = &'b V>,
+    {
+        let name = name.as_ref();
+        if !self.list_exists(name) {
+            return Ok(false);
+        }
+        let items = seq
+            .into_iter()
+            .map(|v| self.ser.serialize_data(v))
+            .collect::<Result<Vec<_>>>()?;
+        if let Some(list) = self.list_mut(name) {
+            list.extend(items.iter().cloned());
+        }
+        self.ops.push(LogOp::ListExtend {
+            name: name.to_string(),
+            items,
+        });
+        Ok(true)
+    }
+
+    /// Get an item of a list in a certain position, as seen by the transaction.
+    pub fn list_get<V: DeserializeOwned, N: AsRef<str>>(&self, name: N, pos: usize) -> Option<V> {
+        self.ser
+            .deserialize_data(self.list(name.as_ref())?.get(pos)?)
+    }
+
+    /// Get the length of a list, as seen by the transaction.
+    ///
+    /// If the list is empty or if it doesn't exist the value of 0 is returned.
+    pub fn list_len<N: AsRef<str>>(&self, name: N) -> usize {
+        self.list(name.as_ref()).map_or(0, Vec::len)
+    }
+
+    /// Remove a list.
+    ///
+    /// This method returns the number of items that were in the list, or zero (0) if the list
+    /// doesn't exist.
+    pub fn list_rm_list<N: AsRef<str>>(&mut self, name: N) -> usize {
+        let name = name.as_ref();
+        let len = match self.list(name) {
+            Some(list) => list.len(),
+            None => return 0,
+        };
+        self.staged.insert(name.to_string(), Staged::Removed);
+        self.ops.push(LogOp::ListRem {
+            name: name.to_string(),
+        });
+        len
+    }
+
+    /// Pop an item out of a list.
+    ///
+    /// If the list is not found or the given position is out of bounds no item will be removed
+    /// and `None` will be returned. Otherwise the item will be removed and `Some(V)` will be returned.
+    pub fn list_pop<V: DeserializeOwned, N: AsRef<str>>(
+        &mut self,
+        name: N,
+        pos: usize,
+    ) -> Option<V> {
+        let name = name.as_ref();
+        if pos >= self.list_len(name) {
+            return None;
+        }
+        let item = self.list_mut(name)?.remove(pos);
+        self.ops.push(LogOp::ListRemove {
+            name: name.to_string(),
+            pos,
+        });
+        self.ser.deserialize_data(&item)
+    }
+
+    /// Remove the first instance of a value out of a list.
+    ///
+    /// This method returns `Ok(true)` if the item was removed, `Ok(false)` if the list or the value
+    /// isn't found, and an `anyhow::Error` if the value can't be serialized.
+    pub fn list_rm_val<V: Serialize, N: AsRef<str>>(&mut self, name: N, value: &V) -> Result<bool> {
+        let name = name.as_ref();
+        let data = self.ser.serialize_data(value)?;
+        let pos = match self
+            .list(name)
+            .and_then(|list| list.iter().position(|item| *item == data))
+        {
+            Some(pos) => pos,
+            None => return Ok(false),
+        };
+        if let Some(list) = self.list_mut(name) {
+            list.remove(pos);
+        }
+        self.ops.push(LogOp::ListRemove {
+            name: name.to_string(),
+            pos,
+        });
+        Ok(true)
+    }
+
+    fn value(&self, key: &str) -> Option<&Vec<u8>> {
+        match self.staged.get(key) {
+            Some(Staged::Value(value)) => Some(value),
+            Some(_) => None,
+            None if is_expired(self.expires, key, self.now) => None,
+            None => self.map.get(key),
+        }
+    }
+
+    fn list(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
+        match self.staged.get(name) {
+            Some(Staged::List(list)) => Some(list),
+            Some(_) => None,
+            None if is_expired(self.expires, name, self.now) => None,
+            None => self.list_map.get(name),
+        }
+    }
+
+    fn members(&self, name: &str) -> Option<&HashSet<Vec<u8>>> {
+        match self.staged.get(name) {
+            Some(_) => None,
+            None if is_expired(self.expires, name, self.now) => None,
+            None => self.set_map.get(name),
+        }
+    }
+
+    fn hash(&self, key: &str) -> Option<&HashMap<String, Vec<u8>>> {
+        match self.staged.get(key) {
+            Some(_) => None,
+            None if is_expired(self.expires, key, self.now) => None,
+            None => self.hash_map.get(key),
+        }
+    }
+
+    fn zset(&self, key: &str) -> Option<&SortedSet> {
+        match self.staged.get(key) {
+            Some(_) => None,
+            None if is_expired(self.expires, key, self.now) => None,
+            None => self.zset_map.get(key),
+        }
+    }
+
+    /// Returns the staged copy of a list, copying it from the DB first if needed.
+    fn list_mut(&mut self, name: &str) -> Option<&mut Vec<Vec<u8>>> {
+        if !self.staged.contains_key(name) {
+            let list = self.list(name)?.clone();
+            self.staged.insert(name.to_string(), Staged::List(list));
+        }
+        match self.staged.get_mut(name) {
+            Some(Staged::List(list)) => Some(list),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::bail;
+
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    fn load(dir: &TempDir) -> NoDb {
+        NoDb::load(
+            dir.path("test.db"),
+            DumpPolicy::Never,
+            SerializationMethod::Json,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn reads_see_the_staged_changes() {
+        let dir = TempDir::new("txn-reads");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+
+        db.transaction(|tx| {
+            tx.set("a", 10)?;
+            assert_eq!(tx.get::<_, i32>("a"), Some(10));
+            assert!(tx.rem("b"));
+            assert!(!tx.exists("b"));
+            assert!(!tx.rem("b"));
+            tx.list_create("list");
+            tx.list_extend("list", &[1, 2, 3])?;
+            assert_eq!(tx.list_pop::<i32, _>("list", 0), Some(1));
+            assert_eq!(tx.list_len("list"), 2);
+            // A value set over the list replaces it.
+            tx.set("list", 4)?;
+            assert!(!tx.list_exists("list"));
+            Ok(())
+        })
+        .unwrap();
+        drop(db);
+
+        let db = load(&dir);
+        assert_eq!(db.get::<_, i32>("a"), Some(10));
+        assert!(!db.exists("b"));
+        assert_eq!(db.get::<_, i32>("list"), Some(4));
+        assert!(!db.list_exists("list"));
+    }
+
+    #[test]
+    fn failed_transactions_change_nothing() {
+        let dir = TempDir::new("txn-rollback");
+        let mut db = NoDb::new(
+            dir.path("test.db"),
+            DumpPolicy::Auto,
+            SerializationMethod::Json,
+        )
+        .unwrap();
+        db.set("alice", 100).unwrap();
+        db.set("bob", 50).unwrap();
+
+        let transfer = |db: &mut NoDb, amount: i32| {
+            db.transaction(|tx| {
+                let alice = tx.get::<_, i32>("alice").unwrap_or(0);
+                let bob = tx.get::<_, i32>("bob").unwrap_or(0);
+                tx.set("bob", bob + amount)?;
+                if alice < amount {
+                    bail!("insufficient funds");
+                }
+                tx.set("alice", alice - amount)?;
+                Ok(())
+            })
+        };
+        transfer(&mut db, 30).unwrap();
+        assert!(transfer(&mut db, 300).is_err());
+        assert_eq!(db.get::<_, i32>("alice"), Some(70));
+        assert_eq!(db.get::<_, i32>("bob"), Some(80));
+        drop(db);
+
+        let db = load(&dir);
+        assert_eq!(db.get::<_, i32>("alice"), Some(70));
+        assert_eq!(db.get::<_, i32>("bob"), Some(80));
+    }
+}
@@ -0,0 +1,520 @@
+//! # Write-Ahead Log
+//!
+//! An append-only log of the changes made to a NoDb instance since its last full dump.
+//!
+//! The log lives next to the DB file (`<db>.log`). Its first line holds a checksum of the
+//! snapshot it was written on top of, followed by one base64 encoded record per line, each
+//! record being a [LogOp] serialized with the DB's serialization method. A log whose checksum
+//! doesn't match the current snapshot is stale and is ignored, and a torn final record left by
+//! a crash in the middle of an append is dropped.
+
+use std::{
+    ffi::OsString,
+    fs::{read, remove_file, File, OpenOptions},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    crypto::{checksum, B64},
+    data::DbDataMut,
+    nodb::Durability,
+    ser::{SerializeMethod, Serializer},
+};
+
+/// A single change made to the DB.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) enum LogOp {
+    Set {
+        key: String,
+        value: Vec<u8>,
+    },
+    Rem {
+        key: String,
+    },
+    ListCreate {
+        name: String,
+    },
+    ListExtend {
+        name: String,
+        items: Vec<Vec<u8>>,
+    },
+    ListRemove {
+        name: String,
+        pos: usize,
+    },
+    ListRem {
+        name: String,
+    },
+    /// Adds members to a set, creating it if needed.
+    SetAdd {
+        name: String,
+        members: Vec<Vec<u8>>,
+    },
+    /// Removes members from a set, removing the set once it is empty.
+    SetRem {
+        name: String,
+        members: Vec<Vec<u8>>,
+    },
+    /// Sets fields of a hash, creating it if needed.
+    HashPut {
+        name: String,
+        fields: Vec<(String, Vec<u8>)>,
+    },
+    /// Removes fields from a hash, removing the hash once it is empty.
+    HashDel {
+        name: String,
+        fields: Vec<String>,
+    },
+    /// Adds members to a sorted set with their scores, or changes their scores, creating the sorted
+    /// set if needed.
+    ZSetAdd {
+        name: String,
+        members: Vec<(Vec<u8>, f64)>,
+    },
+    /// Removes members from a sorted set, removing the sorted set once it is empty.
+    ZSetRem {
+        name: String,
+        members: Vec<Vec<u8>>,
+    },
+    /// Sets the time the key expires, or makes it persistent.
+    Expire {
+        key: String,
+        at: Option<u64>,
+    },
+    /// Several changes applied together, written as a single record so that they are replayed
+    /// either all or not at all.
+    Batch {
+        ops: Vec<LogOp>,
+    },
+}
+
+impl LogOp {
+    /// Returns the keys of the values, lists, sets, hashes and sorted sets that are changed.
+    pub(crate) fn keys(&self) -> Vec<&str> {
+        match self {
+            LogOp::Set { key, .. } | LogOp::Rem { key } | LogOp::Expire { key, .. } => vec![key],
+            LogOp::ListCreate { name }
+            | LogOp::ListExtend { name, .. }
+            | LogOp::ListRemove { name, .. }
+            | LogOp::ListRem { name }
+            | LogOp::SetAdd { name, .. }
+            | LogOp::SetRem { name, .. }
+            | LogOp::HashPut { name, .. }
+            | LogOp::HashDel { name, .. }
+            | LogOp::ZSetAdd { name, .. }
+            | LogOp::ZSetRem { name, .. } => vec![name],
+            LogOp::Batch { ops } => ops.iter().flat_map(LogOp::keys).collect(),
+        }
+    }
+
+    /// Applies the change to the data of a DB.
+    ///
+    /// Setting or removing a value, a list, a set, a hash or a sorted set makes its key persistent.
+    pub(crate) fn apply(self, data: &mut DbDataMut<'_>) {
+        match self {
+            LogOp::Set { key, value } => data.set_value(key, value),
+            LogOp::Rem { key } => data.remove(&key),
+            LogOp::ListCreate { name } => data.set_list(name, Vec::new()),
+            LogOp::ListExtend { name, items } => {
+                if let Some(list) = data.list_map.get_mut(&name) {
+                    list.extend(items);
+                }
+            }
+            LogOp::ListRemove { name, pos } => {
+                if let Some(list) = data.list_map.get_mut(&name) {
+                    if pos < list.len() {
+                        list.remove(pos);
+                    }
+                }
+            }
+            LogOp::ListRem { name } => {
+                if data.list_map.contains_key(&name) {
+                    data.remove(&name);
+                }
+            }
+            LogOp::SetAdd { name, members } => {
+                data.set_map.entry(name).or_default().extend(members);
+            }
+            LogOp::SetRem { name, members } => {
+                if let Some(set) = data.set_map.get_mut(&name) {
+                    for member in members.iter() {
+                        set.remove(member);
+                    }
+                    if set.is_empty() {
+                        data.set_map.remove(&name);
+                        data.expires.remove(&name);
+                    }
+                }
+            }
+            LogOp::HashPut { name, fields } => {
+                data.hash_map.entry(name).or_default().extend(fields);
+            }
+            LogOp::HashDel { name, fields } => {
+                if let Some(hash) = data.hash_map.get_mut(&name) {
+                    for field in fields.iter() {
+                        hash.remove(field);
+                    }
+                    if hash.is_empty() {
+                        data.hash_map.remove(&name);
+                        data.expires.remove(&name);
+                    }
+                }
+            }
+            LogOp::ZSetAdd { name, members } => {
+                let zset = data.zset_map.entry(name).or_default();
+                for (member, score) in members {
+                    zset.insert(member, score);
+                }
+            }
+            LogOp::ZSetRem { name, members } => {
+                if let Some(zset) = data.zset_map.get_mut(&name) {
+                    for member in members.iter() {
+                        zset.remove(member);
+                    }
+                    if zset.is_empty() {
+                        data.zset_map.remove(&name);
+                        data.expires.remove(&name);
+                    }
+                }
+            }
+            LogOp::Expire { key, at } => match at {
+                Some(at) if data.contains_key(&key) => {
+                    data.expires.insert(key, at);
+                }
+                _ => {
+                    data.expires.remove(&key);
+                }
+            },
+            LogOp::Batch { ops } => {
+                for op in ops {
+                    op.apply(data);
+                }
+            }
+        }
+    }
+}
+
+/// A line of the log. Records are wrapped in a struct since some formats (e.g. TOML)
+/// can't serialize an enum at the top level.
+#[derive(Serialize, Deserialize)]
+struct Record {
+    op: LogOp,
+}
+
+/// The write-ahead log of a NoDb instance.
+pub(crate) struct Wal {
+    path: PathBuf,
+    base: u64,
+    base_len: u64,
+    len: u64,
+}
+
+impl Wal {
+    /// Creates the log of a DB that has no snapshot on disk yet.
+    pub(crate) fn new<P: AsRef<Path>>(db_path: P) -> Self {
+        Wal {
+            path: log_path(db_path),
+            base: 0,
+            base_len: 0,
+            len: 0,
+        }
+    }
+
+    /// Opens the log of the DB at `db_path` and replays every record written on top of `snapshot`.
+    ///
+    /// With `salvage` unset, a corrupted record is reported as an `anyhow::Error`. Otherwise it is
+    /// skipped, and the number of skipped records is returned along with the log.
+    ///
+    /// A record torn by a crash is cut off the end of the log, unless `read_only` is set, in which case
+    /// it is only ignored.
+    pub(crate) fn load<P: AsRef<Path>>(
+        db_path: P,
+        snapshot: &[u8],
+        ser: &Serializer,
+        mut data: DbDataMut<'_>,
+        salvage: bool,
+        read_only: bool,
+    ) -> Result<(Self, usize)> {
+        let mut wal = Wal::new(db_path);
+        wal.reset(snapshot);
+
+        let content = match read(&wal.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok((wal, 0)),
+            Err(err) => return Err(err.into()),
+        };
+        let mut lines = content.split_inclusive(|byte| *byte == b'\n');
+        let header = header(wal.base);
+        if lines.next() != Some(header.as_bytes()) {
+            return Ok((wal, 0));
+        }
+
+        let mut end = header.len();
+        let mut corrupted = 0;
+        for line in lines {
+            match line.strip_suffix(b"\n").and_then(|line| decode(ser, line)) {
+                Some(op) => op.apply(&mut data),
+                // The last record may have been torn by a crash while it was being appended.
+                None if end + line.len() == content.len() => break,
+                None if salvage => corrupted += 1,
+                None => return Err(anyhow!("Corrupted record in {}", wal.path.display())),
+            }
+            end += line.len();
+        }
+
+        if end != content.len() && !read_only {
+            OpenOptions::new()
+                .write(true)
+                .open(&wal.path)?
+                .set_len(end as u64)?;
+        }
+        wal.len = end as u64;
+        Ok((wal, corrupted))
+    }
+
+    /// Returns `true` if the log is based on a snapshot that was dumped to the DB file.
+    pub(crate) fn has_snapshot(&self) -> bool {
+        self.base_len != 0
+    }
+
+    /// Returns `true` once the log has grown as large as the snapshot it is based on,
+    /// at which point a full dump is cheaper than replaying the log.
+    pub(crate) fn is_full(&self) -> bool {
+        self.len >= self.base_len
+    }
+
+    /// Appends a record to the log, syncing it to the disk according to `durability`.
+    pub(crate) fn append(
+        &mut self,
+        ser: &Serializer,
+        op: LogOp,
+        durability: Durability,
+    ) -> Result<()> {
+        let record = encode(ser, op)?;
+
+        let mut file = if self.len == 0 {
+            let mut file = File::create(&self.path)?;
+            let header = header(self.base);
+            file.write_all(header.as_bytes())?;
+            durability.sync_dir(&self.path)?;
+            self.len = header.len() as u64;
+            file
+        } else {
+            OpenOptions::new().append(true).open(&self.path)?
+        };
+
+        if let Err(err) = file
+            .write_all(record.as_bytes())
+            .map_err(Into::into)
+            .and_then(|_| durability.sync_file(&file))
+        {
+            let _ = file.set_len(self.len);
+            return Err(err);
+        }
+        self.len += record.len() as u64;
+        Ok(())
+    }
+
+    /// Discards the log after `snapshot` was dumped to the DB file.
+    pub(crate) fn reset(&mut self, snapshot: &[u8]) {
+        self.base = checksum(snapshot);
+        self.base_len = snapshot.len() as u64;
+        if self.len != 0 {
+            let _ = remove_file(&self.path);
+            self.len = 0;
+        }
+    }
+}
+
+fn log_path<P: AsRef<Path>>(db_path: P) -> PathBuf {
+    let mut path = OsString::from(db_path.as_ref().as_os_str());
+    path.push(".log");
+    PathBuf::from(path)
+}
+
+/// Encodes a change as a line of the log.
+pub(crate) fn encode(ser: &Serializer, op: LogOp) -> Result<String> {
+    let mut record = B64.encrypt(ser.serialize_data(&Record { op })?);
+    record.push('\n');
+    Ok(record)
+}
+
+/// Decodes a line of the log, without its trailing newline.
+pub(crate) fn decode(ser: &Serializer, line: &[u8]) -> Option<LogOp> {
+    let record = B64.decrypt(line).ok()?;
+    ser.deserialize_data::<Record>(&record)
+        .map(|record| record.op)
+}
+
+/// The first line of a log written on top of a file with the given checksum.
+pub(crate) fn header(base: u64) -> String {
+    format!("{:016x}\n", base)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{copy, metadata, read, write, OpenOptions},
+        io::Write,
+        path::Path,
+    };
+
+    use crate::{testing::TempDir, DumpPolicy, NoDb, SerializationMethod};
+
+    /// Copies a DB and its log, as they would be found after a crash.
+    fn crash_copy(from: &Path, to: &Path) {
+        copy(from, to).unwrap();
+        copy(super::log_path(from), super::log_path(to)).unwrap();
+    }
+
+    fn load(path: &Path) -> NoDb {
+        NoDb::load(path, DumpPolicy::Never, SerializationMethod::Json).unwrap()
+    }
+
+    /// Creates a DB whose file is large enough for the log to hold a few changes before it is full.
+    fn new(path: &Path) -> NoDb {
+        let mut db = NoDb::new(path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("padding", "x".repeat(1000)).unwrap();
+        db
+    }
+
+    #[test]
+    fn changes_are_appended_and_replayed() {
+        let dir = TempDir::new("wal-replay");
+        let path = dir.path("test.db");
+        let mut db = new(&path);
+        let snapshot = read(&path).unwrap();
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        db.rem("a").unwrap();
+        db.list_create("list").unwrap();
+        db.list_extend("list", &[1, 2, 3]).unwrap();
+        db.list_pop::<i32, _>("list", 0).unwrap();
+
+        // The changes were appended to the log, leaving the file as it was dumped.
+        assert_eq!(read(&path).unwrap(), snapshot);
+        crash_copy(&path, &dir.path("copy.db"));
+        let copy = load(&dir.path("copy.db"));
+        assert_eq!(copy.get::<_, i32>("a"), None);
+        assert_eq!(copy.get::<_, i32>("b"), Some(2));
+        assert_eq!(copy.list_get::<i32, _>("list", 0), Some(2));
+        assert_eq!(copy.list_len("list"), 2);
+    }
+
+    #[test]
+    fn a_full_log_is_folded_into_the_file() {
+        let dir = TempDir::new("wal-full");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::Auto, SerializationMethod::Json).unwrap();
+        db.set("a", 1).unwrap();
+        let snapshot = read(&path).unwrap();
+        for i in 0..100 {
+            db.set("a", i).unwrap();
+        }
+
+        // The log never grows much larger than the file, which was dumped again in the meantime.
+        let log = metadata(super::log_path(&path)).map_or(0, |log| log.len());
+        assert!(log < metadata(&path).unwrap().len() * 2);
+        assert_ne!(read(&path).unwrap(), snapshot);
+        drop(db);
+        assert_eq!(load(&path).get::<_, i32>("a"), Some(99));
+    }
+
+    #[test]
+    fn a_torn_record_is_dropped() {
+        let dir = TempDir::new("wal-torn");
+        let path = dir.path("test.db");
+        let mut db = new(&path);
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        let copy = dir.path("copy.db");
+        crash_copy(&path, &copy);
+        let log = super::log_path(&copy);
+        let len = metadata(&log).unwrap().len();
+        OpenOptions::new()
+            .append(true)
+            .open(&log)
+            .unwrap()
+            .write_all(b"eyJvc")
+            .unwrap();
+
+        // Read-only instances don't write to the log.
+        let db = NoDb::load_read_only(&copy, SerializationMethod::Json).unwrap();
+        assert_eq!(db.get::<_, i32>("b"), Some(2));
+        assert_eq!(metadata(&log).unwrap().len(), len + 5);
+        drop(db);
+
+        let db = load(&copy);
+        assert_eq!(db.get::<_, i32>("b"), Some(2));
+        assert_eq!(metadata(&log).unwrap().len(), len);
+    }
+
+    #[test]
+    fn a_corrupted_record_is_an_error_unless_salvaged() {
+        let dir = TempDir::new("wal-corrupted");
+        let path = dir.path("test.db");
+        let mut db = new(&path);
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        db.set("c", 3).unwrap();
+        let path = dir.path("copy.db");
+        crash_copy(&dir.path("test.db"), &path);
+        let log = super::log_path(&path);
+        let content = String::from_utf8(read(&log).unwrap()).unwrap();
+        let mut lines = content.lines().collect::<Vec<_>>();
+        // The first record, which sets `a`, is corrupted.
+        lines[1] = "not a record";
+        write(&log, lines.join("\n") + "\n").unwrap();
+
+        assert!(NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).is_err());
+        let (db, report) =
+            NoDb::load_salvage(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+        assert_eq!(report.corrupted_records, 1);
+        assert!(!report.is_clean());
+        assert_eq!(db.get::<_, i32>("a"), None);
+        assert_eq!(db.get::<_, i32>("b"), Some(2));
+        assert_eq!(db.get::<_, i32>("c"), Some(3));
+    }
+
+    #[test]
+    fn a_stale_log_is_ignored() {
+        let dir = TempDir::new("wal-stale");
+        let path = dir.path("test.db");
+        let mut db = new(&path);
+        db.set("a", 1).unwrap();
+        db.set("b", 2).unwrap();
+        let stale = read(super::log_path(&path)).unwrap();
+        db.dump().unwrap();
+        db.set("b", 3).unwrap();
+        db.close().unwrap();
+        write(super::log_path(&path), stale).unwrap();
+
+        // The log was written on top of the previous dump, so its records are out of date.
+        let db = load(&path);
+        assert_eq!(db.get::<_, i32>("b"), Some(3));
+    }
+
+    #[test]
+    fn the_journal_keeps_changes_until_the_next_dump() {
+        let dir = TempDir::new("wal-journal");
+        let path = dir.path("test.db");
+        let mut db = NoDb::new(&path, DumpPolicy::OnCall, SerializationMethod::Json)
+            .and_then(NoDb::with_journal)
+            .unwrap();
+        db.set("a", 1).unwrap();
+        db.list_create("list").unwrap();
+        db.list_add("list", &2).unwrap();
+
+        crash_copy(&path, &dir.path("copy.db"));
+        let copy = load(&dir.path("copy.db"));
+        assert_eq!(copy.get::<_, i32>("a"), Some(1));
+        assert_eq!(copy.list_get::<i32, _>("list", 0), Some(2));
+
+        db.dump().unwrap();
+        assert!(!super::log_path(&path).exists());
+    }
+}
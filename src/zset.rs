@@ -0,0 +1,217 @@
+//! # Sorted Set
+//!
+//! A set of members ordered by a score, as held by the sorted sets of a NoDb instance.
+
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::{Bound, RangeBounds},
+};
+
+/// A score, ordered with [f64::total_cmp()] so that scores can be kept in a B-tree.
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// A set of serialized members, each with a score, ordered by score and then by member.
+///
+/// Adding, removing or rescoring a member takes a logarithmic time, as members are indexed both by their
+/// value and by their score. The B-tree doesn't count the members below a node though, so finding the
+/// rank of a member or the members at a rank takes a time proportional to the rank.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    order: BTreeSet<(Score, Vec<u8>)>,
+}
+
+impl SortedSet {
+    /// Creates an empty sorted set.
+    pub(crate) fn new() -> Self {
+        SortedSet::default()
+    }
+
+    /// Returns the number of members.
+    pub(crate) fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    /// Returns `true` if the set holds no member.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Returns the score of a member, or `None` if the set doesn't hold it.
+    pub(crate) fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Adds a member with a score, or changes the score of a member the set already holds.
+    ///
+    /// Returns the previous score of the member, if any.
+    pub(crate) fn insert(&mut self, member: Vec<u8>, score: f64) -> Option<f64> {
+        // -0.0 is ordered before 0.0 by total_cmp(), though they are equal scores.
+        let score = score + 0.0;
+        let previous = self.scores.insert(member.clone(), score);
+        if let Some(previous) = previous {
+            self.order.remove(&(Score(previous), member.clone()));
+        }
+        self.order.insert((Score(score), member));
+        previous
+    }
+
+    /// Removes a member, returning its score if the set held it.
+    pub(crate) fn remove(&mut self, member: &[u8]) -> Option<f64> {
+        let score = self.scores.remove(member)?;
+        self.order.remove(&(Score(score), member.to_vec()));
+        Some(score)
+    }
+
+    /// Returns the position of a member, the member with the lowest score being at position 0.
+    ///
+    /// The members ranked before it are counted one by one.
+    pub(crate) fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(self.order.range(..(Score(score), member.to_vec())).count())
+    }
+
+    /// Returns the members along with their scores, from the lowest score to the highest.
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = (&[u8], f64)> {
+        self.order
+            .iter()
+            .map(|(score, member)| (member.as_slice(), score.0))
+    }
+
+    /// Returns the members whose position is within a range, from the lowest score to the highest.
+    ///
+    /// The members ranked before the range are skipped one by one.
+    pub(crate) fn range_by_rank<R: RangeBounds<usize>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (&[u8], f64)> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => *start,
+            Bound::Excluded(start) => start.saturating_add(1),
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(end) => end.saturating_add(1),
+            Bound::Excluded(end) => *end,
+            Bound::Unbounded => usize::MAX,
+        };
+        self.iter().skip(start).take(end.saturating_sub(start))
+    }
+
+    /// Returns the members whose score is within a range, from the lowest score to the highest.
+    pub(crate) fn range_by_score<R: RangeBounds<f64>>(
+        &self,
+        range: R,
+    ) -> impl Iterator<Item = (&[u8], f64)> {
+        let start = range.start_bound().cloned();
+        let end = range.end_bound().cloned();
+        let first = match start {
+            Bound::Included(start) | Bound::Excluded(start) => {
+                Bound::Included((Score(start + 0.0), Vec::new()))
+            }
+            Bound::Unbounded => Bound::Unbounded,
+        };
+        self.order
+            .range((first, Bound::Unbounded))
+            .map(|(score, member)| (member.as_slice(), score.0))
+            .skip_while(
+                move |(_, score)| matches!(start, Bound::Excluded(start) if *score <= start),
+            )
+            .take_while(move |(_, score)| match end {
+                Bound::Included(end) => *score <= end,
+                Bound::Excluded(end) => *score < end,
+                Bound::Unbounded => true,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound::{Excluded, Included, Unbounded};
+
+    use super::SortedSet;
+
+    fn zset() -> SortedSet {
+        let mut zset = SortedSet::new();
+        for (member, score) in [("c", 2.0), ("a", 1.0), ("b", 1.0), ("z", -0.0), ("d", 3.0)] {
+            zset.insert(member.as_bytes().to_vec(), score);
+        }
+        zset
+    }
+
+    fn members<'a>(iter: impl Iterator<Item = (&'a [u8], f64)>) -> String {
+        iter.map(|(member, _)| String::from_utf8_lossy(member).into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn members_are_ordered_by_score_then_member() {
+        let mut zset = zset();
+        assert_eq!(members(zset.iter()), "zabcd");
+        assert_eq!(zset.rank(b"z"), Some(0));
+        assert_eq!(zset.rank(b"b"), Some(2));
+        assert_eq!(zset.rank(b"missing"), None);
+
+        assert_eq!(zset.insert(b"a".to_vec(), 4.0), Some(1.0));
+        assert_eq!(members(zset.iter()), "zbcda");
+        assert_eq!(zset.remove(b"c"), Some(2.0));
+        assert_eq!(zset.remove(b"c"), None);
+        assert_eq!(members(zset.iter()), "zbda");
+        assert_eq!(zset.len(), 4);
+    }
+
+    #[test]
+    fn ranges_by_rank_honor_their_bounds() {
+        let zset = zset();
+        assert_eq!(members(zset.range_by_rank(1..3)), "ab");
+        assert_eq!(members(zset.range_by_rank(1..=3)), "abc");
+        assert_eq!(members(zset.range_by_rank(3..)), "cd");
+        assert_eq!(members(zset.range_by_rank((Excluded(0), Included(1)))), "a");
+        assert_eq!(members(zset.range_by_rank(4..10)), "d");
+        assert_eq!(members(zset.range_by_rank((Included(3), Excluded(1)))), "");
+        assert_eq!(members(zset.range_by_rank(usize::MAX..)), "");
+    }
+
+    #[test]
+    fn ranges_by_score_honor_their_bounds() {
+        let zset = zset();
+        assert_eq!(members(zset.range_by_score(1.0..=2.0)), "abc");
+        assert_eq!(members(zset.range_by_score(1.0..2.0)), "ab");
+        assert_eq!(
+            members(zset.range_by_score((Excluded(1.0), Unbounded))),
+            "cd"
+        );
+        assert_eq!(members(zset.range_by_score(..1.0)), "z");
+        assert_eq!(members(zset.range_by_score(2.5..2.6)), "");
+        // -0.0 and 0.0 are the same score.
+        assert_eq!(members(zset.range_by_score(0.0..1.0)), "z");
+        assert_eq!(members(zset.range_by_score(-0.0..=0.0)), "z");
+        assert_eq!(
+            members(zset.range_by_score((Excluded(-0.0), Unbounded))),
+            "abcd"
+        );
+        assert_eq!(zset.score(b"z").map(f64::is_sign_negative), Some(false));
+    }
+}
@@ -0,0 +1,392 @@
+//! Every mutating method of `NoDb` must leave the DB unchanged when the dump it triggers fails.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{create_dir, create_dir_all, remove_dir_all, remove_file},
+    path::PathBuf,
+    process,
+    time::Duration,
+};
+
+use nodb::{DropPolicy, DumpPolicy, NoDb, SerializationMethod};
+use serde_json::Value;
+
+type State = (
+    BTreeMap<String, Vec<u8>>,
+    BTreeMap<String, Vec<Vec<u8>>>,
+    BTreeMap<String, BTreeSet<String>>,
+    BTreeMap<String, BTreeMap<String, String>>,
+    BTreeMap<String, Vec<(String, f64)>>,
+    Vec<String>,
+    Vec<String>,
+);
+
+struct Fixture {
+    dir: PathBuf,
+    db: NoDb,
+}
+
+impl Fixture {
+    /// Creates a DB holding a few values, lists, sets, hashes and sorted sets, whose dumps fail from then on.
+    fn new(name: &str, policy: DumpPolicy) -> Self {
+        let dir =
+            std::env::temp_dir().join(format!("nodb-dump-failures-{}-{}", process::id(), name));
+        let _ = remove_dir_all(&dir);
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("test.db");
+
+        let mut db = NoDb::new(&path, policy, SerializationMethod::Json).unwrap();
+        db.set("num", 1).unwrap();
+        db.set("text", "hello").unwrap();
+        db.list_create("list").unwrap();
+        db.list_extend("list", &[1, 2, 3]).unwrap();
+        db.list_create("empty").unwrap();
+        db.set_add("tags", &"a").unwrap();
+        db.set_add("tags", &"b").unwrap();
+        db.set_add("single", &"a").unwrap();
+        db.hset("user", "name", "alice").unwrap();
+        db.hset("user", "age", 30).unwrap();
+        db.hset("flag", "on", true).unwrap();
+        db.zadd("scores", &"alice", 42.0).unwrap();
+        db.zadd("scores", &"bob", 17.0).unwrap();
+        db.zadd("last", &"carol", 1.0).unwrap();
+        db.set_with_ttl("session", "token", Duration::from_secs(3600))
+            .unwrap();
+        db.dump().unwrap();
+        db.set("pending", true).unwrap();
+        db.backup_to(dir.join("backup.db")).unwrap();
+
+        // The DB file and its write-ahead log can't be written once they are directories.
+        let log = dir.join("test.db.log");
+        remove_file(&path).unwrap();
+        create_dir(&path).unwrap();
+        let _ = remove_file(&log);
+        create_dir(&log).unwrap();
+
+        db.drop_policy = DropPolicy::Ignore;
+        Fixture { dir, db }
+    }
+
+    /// Runs `f` on the DB of every fixture and checks that it failed without changing the DB.
+    fn check<T, F>(name: &str, f: F)
+    where
+        F: Fn(&mut NoDb) -> Option<T>,
+    {
+        for (policy_name, policy) in [
+            ("auto", DumpPolicy::Auto),
+            ("periodic", DumpPolicy::Periodic(Duration::ZERO)),
+        ] {
+            let Fixture { dir, mut db } =
+                Fixture::new(&format!("{}-{}", name, policy_name), policy);
+            let state = state(&db);
+            let dirty = db.is_dirty();
+            assert!(
+                f(&mut db).is_none(),
+                "{} succeeded with {}",
+                name,
+                policy_name
+            );
+            assert!(
+                self::state(&db) == state,
+                "{} changed the DB with {}",
+                name,
+                policy_name
+            );
+            assert_eq!(db.is_dirty(), dirty);
+            drop(db);
+            let _ = remove_dir_all(dir);
+        }
+    }
+}
+
+fn state(db: &NoDb) -> State {
+    let mut dirty_keys = db.dirty_keys();
+    dirty_keys.sort();
+    let mut expiring_keys = db
+        .get_all()
+        .into_iter()
+        .filter(|key| db.ttl(key).is_some())
+        .collect::<Vec<_>>();
+    expiring_keys.sort();
+    (
+        db.map
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+        db.list_map
+            .iter()
+            .map(|(name, list)| (name.clone(), list.clone()))
+            .collect(),
+        db.get_all()
+            .into_iter()
+            .filter(|key| db.set_len(key) > 0)
+            .map(|key| {
+                let members = db.set_members::<Value, _>(&key);
+                (key, members.iter().map(Value::to_string).collect())
+            })
+            .collect(),
+        db.get_all()
+            .into_iter()
+            .filter(|key| !db.hkeys(key).is_empty())
+            .map(|key| {
+                let hash = db.hgetall::<Value, _>(&key);
+                (
+                    key,
+                    hash.into_iter().map(|(f, v)| (f, v.to_string())).collect(),
+                )
+            })
+            .collect(),
+        db.get_all()
+            .into_iter()
+            .filter(|key| db.zlen(key) > 0)
+            .map(|key| {
+                let members = db.zrange_by_rank(&key, ..);
+                (key, members)
+            })
+            .collect(),
+        dirty_keys,
+        expiring_keys,
+    )
+}
+
+#[test]
+fn set() {
+    Fixture::check("set-new", |db| db.set("new", 1).ok());
+    Fixture::check("set-value", |db| db.set("num", 2).ok());
+    Fixture::check("set-list", |db| db.set("list", 2).ok());
+    Fixture::check("set-set", |db| db.set("tags", 2).ok());
+    Fixture::check("set-hash", |db| db.set("user", 2).ok());
+    Fixture::check("set-zset", |db| db.set("scores", 2).ok());
+}
+
+#[test]
+fn rem() {
+    Fixture::check("rem-value", |db| db.rem("num").ok());
+    Fixture::check("rem-list", |db| db.rem("list").ok());
+    Fixture::check("rem-set", |db| db.rem("tags").ok());
+    Fixture::check("rem-hash", |db| db.rem("user").ok());
+    Fixture::check("rem-zset", |db| db.rem("scores").ok());
+}
+
+#[test]
+fn rem_keys() {
+    Fixture::check("rem_prefix", |db| db.rem_prefix("s").ok());
+    Fixture::check("rem_matching", |db| db.rem_matching("*e*").ok());
+}
+
+#[test]
+fn list_create() {
+    Fixture::check("list_create-new", |db| {
+        db.list_create("new").ok().map(|_| ())
+    });
+    Fixture::check("list_create-value", |db| {
+        db.list_create("num").ok().map(|_| ())
+    });
+    Fixture::check("list_create-list", |db| {
+        db.list_create("list").ok().map(|_| ())
+    });
+}
+
+#[test]
+fn list_add() {
+    Fixture::check("list_add", |db| db.list_add("list", &4).map(|_| ()));
+    Fixture::check("list_add-empty", |db| db.list_add("empty", &4).map(|_| ()));
+}
+
+#[test]
+fn list_extend() {
+    Fixture::check("list_extend", |db| {
+        db.list_extend("list", &[4, 5]).map(|_| ())
+    });
+}
+
+#[test]
+fn list_rm_list() {
+    Fixture::check("list_rm_list", |db| db.list_rm_list("list").ok());
+}
+
+#[test]
+fn list_pop() {
+    Fixture::check("list_pop", |db| db.list_pop::<i32, _>("list", 1));
+}
+
+#[test]
+fn list_rm_val() {
+    Fixture::check("list_rm_val", |db| db.list_rm_val("list", &2).ok());
+}
+
+#[test]
+fn set_add() {
+    Fixture::check("set_add-new", |db| db.set_add("new", &"a").ok());
+    Fixture::check("set_add", |db| db.set_add("tags", &"c").ok());
+}
+
+#[test]
+fn set_rem() {
+    Fixture::check("set_rem", |db| db.set_rem("tags", &"a").ok());
+    Fixture::check("set_rem-last", |db| db.set_rem("single", &"a").ok());
+}
+
+#[test]
+fn hset() {
+    Fixture::check("hset-new", |db| db.hset("new", "name", "bob").ok());
+    Fixture::check("hset", |db| db.hset("user", "name", "bob").ok());
+    Fixture::check("hset-field", |db| db.hset("user", "city", "paris").ok());
+}
+
+#[test]
+fn hdel() {
+    Fixture::check("hdel", |db| db.hdel("user", "name").ok());
+    Fixture::check("hdel-last", |db| db.hdel("flag", "on").ok());
+}
+
+#[test]
+fn hincr_by() {
+    Fixture::check("hincr_by", |db| db.hincr_by("user", "age", 1).ok());
+    Fixture::check("hincr_by-new", |db| db.hincr_by("new", "count", 1).ok());
+}
+
+#[test]
+fn zadd() {
+    Fixture::check("zadd-new", |db| db.zadd("new", &"alice", 1.0).ok());
+    Fixture::check("zadd", |db| db.zadd("scores", &"carol", 1.0).ok());
+    Fixture::check("zadd-score", |db| db.zadd("scores", &"alice", 1.0).ok());
+    Fixture::check("zincr_by", |db| db.zincr_by("scores", &"bob", 1.0).ok());
+}
+
+#[test]
+fn zrem() {
+    Fixture::check("zrem", |db| db.zrem("scores", &"alice").ok());
+    Fixture::check("zrem-last", |db| db.zrem("last", &"carol").ok());
+    Fixture::check("zpop_min", |db| db.zpop_min::<String, _>("scores").ok());
+    Fixture::check("zpop_max", |db| db.zpop_max::<String, _>("last").ok());
+}
+
+#[test]
+fn conditional_writes() {
+    Fixture::check("set_if_absent", |db| db.set_if_absent("new", 1).ok());
+    Fixture::check("set_if_eq", |db| db.set_if_eq("num", &1, &2).ok());
+    Fixture::check("rem_if_eq", |db| db.rem_if_eq("num", &1).ok());
+    Fixture::check("set_if_version", |db| {
+        db.set_if_version("num", db.version("num"), 2).ok()
+    });
+    Fixture::check("rem_if_version", |db| {
+        db.rem_if_version("list", db.version("list")).ok()
+    });
+}
+
+#[test]
+fn ttl() {
+    Fixture::check("set_with_ttl", |db| {
+        db.set_with_ttl("num", 2, Duration::from_secs(60)).ok()
+    });
+    Fixture::check("expire", |db| {
+        db.expire("list", Duration::from_secs(60)).ok()
+    });
+    Fixture::check("expire-set", |db| {
+        db.expire("tags", Duration::from_secs(60)).ok()
+    });
+    Fixture::check("expire-hash", |db| {
+        db.expire("user", Duration::from_secs(60)).ok()
+    });
+    Fixture::check("expire-zset", |db| {
+        db.expire("scores", Duration::from_secs(60)).ok()
+    });
+    Fixture::check("persist", |db| db.persist("session").ok());
+    Fixture::check("set-expiring", |db| db.set("session", "other").ok());
+}
+
+#[test]
+fn counters() {
+    Fixture::check("incr-new", |db| db.incr("new").ok());
+    Fixture::check("incr_by", |db| db.incr_by("num", 5).ok());
+    Fixture::check("decr_by", |db| db.decr_by("num", 5).ok());
+    Fixture::check("incr_by_float", |db| db.incr_by_float("num", 0.5).ok());
+}
+
+#[test]
+fn transaction() {
+    Fixture::check("transaction", |db| {
+        db.transaction(|tx| {
+            tx.set("new", 1)?;
+            tx.rem("num");
+            tx.list_add("list", &4)?;
+            tx.list_pop::<i32, _>("list", 0);
+            tx.list_create("text");
+            tx.rem("tags");
+            tx.rem("user");
+            tx.rem("scores");
+            Ok(())
+        })
+        .ok()
+    });
+}
+
+#[test]
+fn write_batch() {
+    Fixture::check("write_batch", |db| {
+        let mut batch = db.batch();
+        batch.set("new", 1).ok()?;
+        batch.rem("num").list_rm_list("empty");
+        batch.list_add("list", &4).ok()?;
+        db.write_batch(batch).ok()
+    });
+}
+
+#[test]
+fn restore_from() {
+    Fixture::check("restore_from", |db| {
+        let fixture_dir = db.path.parent()?.to_path_buf();
+        db.restore_from(fixture_dir.join("backup.db")).ok()
+    });
+}
+
+#[test]
+fn failed_writes_are_not_dumped_later() {
+    let Fixture { dir, mut db } = Fixture::new("later", DumpPolicy::Auto);
+    assert!(db.set("new", 1).is_err());
+    assert!(db.rem("list").is_err());
+    assert!(db.set_rem("tags", &"a").is_err());
+    assert!(db.hset("user", "name", "bob").is_err());
+    assert!(db.zincr_by("scores", &"bob", 100.0).is_err());
+
+    // Once the DB can be written again, the failed changes don't resurface.
+    let path = db.path.clone();
+    remove_dir_all(&path).unwrap();
+    remove_dir_all(dir.join("test.db.log")).unwrap();
+    db.dump().unwrap();
+    db.set("other", 2).unwrap();
+    db.close().unwrap();
+
+    let db = NoDb::load(&path, DumpPolicy::Never, SerializationMethod::Json).unwrap();
+    assert!(!db.exists("new"));
+    assert_eq!(db.list_len("list"), 3);
+    assert_eq!(db.set_len("tags"), 2);
+    assert_eq!(
+        db.hget::<String, _, _>("user", "name").as_deref(),
+        Some("alice")
+    );
+    assert_eq!(db.zscore("scores", &"bob"), Some(17.0));
+    assert_eq!(db.get::<_, i32>("other"), Some(2));
+    drop(db);
+    let _ = remove_dir_all(dir);
+}
+
+#[test]
+fn failed_writes_leave_the_key_index_unchanged() {
+    let Fixture { dir, db } = Fixture::new("ordered", DumpPolicy::Auto);
+    let mut db = db.with_ordered_keys();
+    let keys = |db: &NoDb| {
+        db.range("a".."z")
+            .map(|kv| kv.get_key().to_string())
+            .collect::<Vec<_>>()
+    };
+    let before = keys(&db);
+    assert!(db.set("new", 1).is_err());
+    assert!(db.rem("num").is_err());
+    assert!(db.list_create("text").is_err());
+    assert_eq!(keys(&db), before);
+    assert_eq!(before, ["num", "pending", "session", "text"]);
+    drop(db);
+    let _ = remove_dir_all(dir);
+}